@@ -0,0 +1,37 @@
+use std::path::Path;
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+}
+
+fn main() {
+    println!("cargo:rustc-env=BUILD_TARGET={}", std::env::var("TARGET").unwrap());
+
+    if let Some(commit) = git(&["rev-parse", "--short", "HEAD"]) {
+        println!("cargo:rustc-env=GIT_COMMIT={commit}");
+    }
+
+    // Naming any file stops cargo from watching the whole package, so name
+    // this script too.
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Rebuild when HEAD moves: on checkout HEAD itself changes, on commit the
+    // branch it points to does (a loose ref, or packed-refs once packed).
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        let git_dir = Path::new(&git_dir);
+        let mut watched = vec![git_dir.join("HEAD"), git_dir.join("packed-refs")];
+        if let Some(branch) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            watched.push(git_dir.join(branch));
+        }
+        for path in watched.iter().filter(|path| path.exists()) {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+}
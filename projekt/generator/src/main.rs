@@ -1,49 +1,292 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use rand::thread_rng;
 use rand::seq::SliceRandom;
-use strum_macros::EnumString;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
 use std::str::FromStr;
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::fmt::Write;
+use std::io::Write as _;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use bytes::Bytes;
-use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full, LengthLimitError, Limited};
 use hyper::body::Incoming;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::Error;
-use hyper::{body::Body, Method, Request, Response, StatusCode};
-use hyper_util::rt::TokioIo;
-use tokio::net::TcpListener;
+use hyper::header::{
+    HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_EXPOSE_HEADERS, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER, VARY, ETAG, IF_NONE_MATCH,
+};
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 const PORT: u16 = 12346;
+const REGISTRY_URL: &str = "http://127.0.0.1:7878";
+const HEARTBEAT_INTERVAL_SECS: u64 = 30;
+const PEERS_CACHE_SECS: u64 = 10;
+const BODY_LIMIT: usize = 64 * 1024;
+const MAX_EVALUATIONS: usize = 64;
+const MAX_RANGE_LEN: u64 = 1_000_000;
+const MAX_BATCH_ITEMS: usize = 100;
+const REGISTRY_TIMEOUT_SECS: u64 = 5;
+const EVALUATION_TIMEOUT_SECS: u64 = 5;
+const HEADER_READ_TIMEOUT_SECS: u64 = 30;
+const CONNECTION_TIMEOUT_SECS: u64 = 600;
+// For requests to the registry and to peers; a delegated request may need the
+// peer's whole evaluation timeout and then some.
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+const PING_TIMEOUT_MILLIS: u64 = 1000;
+const X_REQUEST_ID: &str = "x-request-id";
+const MAX_DEPTH: usize = 32;
+const THREAD_STACK_SIZE: usize = 8 * 1024 * 1024;
+const RATE_BURST: f64 = 20.0;
+const RATE_SWEEP_SECS: u64 = 60;
+// detect_period compares every candidate period against the whole window, so
+// the window stays far below MAX_RANGE_LEN.
+const MAX_PERIOD_WINDOW: u64 = 10_000;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub heartbeat_interval: Duration,
+    pub registry_timeout: Duration,
+    pub body_limit: usize,
+    pub http2: bool,
+    pub header_read_timeout: Duration,
+    pub keep_alive: bool,
+    pub connection_timeout: Duration,
+    pub max_evaluations: usize,
+    pub evaluation_timeout: Duration,
+    pub max_range_len: u64,
+    pub cors_origin: String,
+    pub no_delegate: bool,
+    pub max_batch_items: usize,
+    pub prefer_fastest_peer: bool,
+    pub max_delegation_peers: usize,
+    pub max_depth: usize,
+    pub registry_url: String,
+    pub rate_limit: f64,
+    pub rate_burst: f64,
+}
+
+// Settings read from the file given with --config, as JSON with the same names
+// as the Config fields and durations in seconds. Anything left out falls back
+// to the default; environment variables override both.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub heartbeat_interval: Option<u64>,
+    pub registry_timeout: Option<u64>,
+    pub body_limit: Option<usize>,
+    pub http2: Option<bool>,
+    pub header_read_timeout: Option<u64>,
+    pub keep_alive: Option<bool>,
+    pub connection_timeout: Option<u64>,
+    pub max_evaluations: Option<usize>,
+    pub evaluation_timeout: Option<u64>,
+    pub max_range_len: Option<u64>,
+    pub cors_origin: Option<String>,
+    pub no_delegate: Option<bool>,
+    pub prefer_fastest_peer: Option<bool>,
+    pub max_delegation_peers: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub registry_url: Option<String>,
+    pub rate_limit: Option<f64>,
+    pub rate_burst: Option<f64>,
+    pub max_batch_items: Option<usize>,
+}
+
+impl ConfigFile {
+    pub fn read(path: &str) -> Result<ConfigFile, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("Can't read config file {}: {}", path, e))?;
+        let file = serde_json::from_str(&contents).map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+        Ok(file)
+    }
+}
+
+impl Config {
+    pub fn projects_url(&self) -> String {
+        format!("{}/project", self.registry_url.trim_end_matches('/'))
+    }
+
+    // Fails on values that don't parse or make no sense, so a typo stops the
+    // generator at startup instead of being ignored.
+    pub fn load(file: ConfigFile) -> Result<Config, String> {
+        let config = Config {
+            heartbeat_interval: Duration::from_secs(env_or("HEARTBEAT_INTERVAL", file.heartbeat_interval.unwrap_or(HEARTBEAT_INTERVAL_SECS))?),
+            registry_timeout: Duration::from_secs(env_or("REGISTRY_TIMEOUT", file.registry_timeout.unwrap_or(REGISTRY_TIMEOUT_SECS))?),
+            body_limit: env_or("BODY_LIMIT", file.body_limit.unwrap_or(BODY_LIMIT))?,
+            http2: env_or("HTTP2", file.http2.unwrap_or(false))?,
+            header_read_timeout: Duration::from_secs(env_or("HEADER_READ_TIMEOUT", file.header_read_timeout.unwrap_or(HEADER_READ_TIMEOUT_SECS))?),
+            keep_alive: env_or("KEEP_ALIVE", file.keep_alive.unwrap_or(true))?,
+            connection_timeout: Duration::from_secs(env_or("CONNECTION_TIMEOUT", file.connection_timeout.unwrap_or(CONNECTION_TIMEOUT_SECS))?),
+            max_evaluations: env_or("MAX_EVALUATIONS", file.max_evaluations.unwrap_or(MAX_EVALUATIONS))?,
+            evaluation_timeout: Duration::from_secs(env_or("EVALUATION_TIMEOUT", file.evaluation_timeout.unwrap_or(EVALUATION_TIMEOUT_SECS))?),
+            max_range_len: env_or("MAX_RANGE_LEN", file.max_range_len.unwrap_or(MAX_RANGE_LEN))?,
+            cors_origin: env_or("CORS_ORIGIN", file.cors_origin.unwrap_or_else(|| "*".to_string()))?,
+            no_delegate: env_or("NO_DELEGATE", file.no_delegate.unwrap_or(false))?,
+            prefer_fastest_peer: env_or("PREFER_FASTEST_PEER", file.prefer_fastest_peer.unwrap_or(false))?,
+            max_delegation_peers: env_or("MAX_DELEGATION_PEERS", file.max_delegation_peers.unwrap_or(usize::MAX))?,
+            max_depth: env_or("MAX_DEPTH", file.max_depth.unwrap_or(MAX_DEPTH))?,
+            registry_url: env_or("REGISTRY_URL", file.registry_url.unwrap_or_else(|| REGISTRY_URL.to_string()))?,
+            rate_limit: env_or("RATE_LIMIT", file.rate_limit.unwrap_or(0.0))?,
+            rate_burst: env_or("RATE_BURST", file.rate_burst.unwrap_or(RATE_BURST))?,
+            max_batch_items: env_or("MAX_BATCH_ITEMS", file.max_batch_items.unwrap_or(MAX_BATCH_ITEMS))?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let zero = [
+            ("HEARTBEAT_INTERVAL", self.heartbeat_interval.is_zero()),
+            ("REGISTRY_TIMEOUT", self.registry_timeout.is_zero()),
+            ("BODY_LIMIT", self.body_limit == 0),
+            ("HEADER_READ_TIMEOUT", self.header_read_timeout.is_zero()),
+            ("CONNECTION_TIMEOUT", self.connection_timeout.is_zero()),
+            ("MAX_EVALUATIONS", self.max_evaluations == 0),
+            ("EVALUATION_TIMEOUT", self.evaluation_timeout.is_zero()),
+            ("MAX_RANGE_LEN", self.max_range_len == 0),
+            ("MAX_DEPTH", self.max_depth == 0),
+            ("MAX_BATCH_ITEMS", self.max_batch_items == 0),
+        ];
+        if let Some((name, _)) = zero.iter().find(|(_, zero)| *zero) {
+            return Err(format!("{} must be positive", name));
+        }
+        if !self.rate_limit.is_finite() || self.rate_limit < 0.0 {
+            return Err("RATE_LIMIT must be a number of requests per second, or 0 for no limit".to_string());
+        }
+        // A bucket that can't hold a whole token would refuse every request.
+        if self.rate_limit > 0.0 && (!self.rate_burst.is_finite() || self.rate_burst < 1.0) {
+            return Err("RATE_BURST must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Path given as `--config PATH` or `--config=PATH`.
+fn config_path() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> Result<T, String> {
+    match std::env::var(key) {
+        Ok(value) => value.parse().map_err(|_| format!("Invalid value {:?} for {}", value, key)),
+        Err(_) => Ok(default),
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct Project {
     pub name: String,
     pub ip: String,
     pub port: u16,
+    // Same for every registration of one node, so the registry can replace
+    // the old entry instead of adding another. Other projects may not send one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug)]
 pub struct Range {
     pub from: u64,
     pub to: u64, 
     pub step: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Range {
+    // Whether index k is one of the indices this range emits.
+    pub fn contains(&self, k: u64) -> bool {
+        k >= self.from && k <= self.to && (k - self.from).is_multiple_of(self.step)
+    }
+
+    // Number of elements the range emits; only meaningful for a nonzero step.
+    pub fn count(&self) -> u64 {
+        if self.from > self.to {
+            0
+        } else {
+            (self.to - self.from) / self.step + 1
+        }
+    }
+}
+
+// Like Range, but from and to may be negative.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug)]
+pub struct SignedRange {
+    pub from: i64,
+    pub to: i64,
+    pub step: u64,
+}
+
+impl SignedRange {
+    // Same as Range::count.
+    pub fn count(&self) -> u64 {
+        if self.from > self.to {
+            0
+        } else {
+            self.to.abs_diff(self.from) / self.step + 1
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct SequenceSyntax {
     pub name: String,
     pub parameters: Vec<f64>,
     pub sequences: Vec<Box<SequenceSyntax>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct SequenceRequest {
     pub range: Range,
     pub parameters: Vec<f64>,
     pub sequences: Vec<Box<SequenceSyntax>>,
+    // Kept when delegating, so one request can be followed across generators.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    // Peers to delegate to instead of the ones listed in the registry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peers: Option<Vec<Project>>,
+    // Replaces range when given. Other projects don't know it, so such
+    // requests are never delegated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_range: Option<SignedRange>,
+    // Exact indices to evaluate, in the given order. Replaces both ranges and
+    // like signed_range is never delegated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indices: Option<Vec<u64>>,
+}
+
+// Body of POST /evaluate, where the root sequence is named in the body instead of the path.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EvaluateRequest {
+    pub range: Range,
+    pub syntax: SequenceSyntax,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -51,35 +294,472 @@ pub struct SequenceInfo {
     name: String,
     description: String,
     parameters: u32,
+    // Name and meaning of each parameter, in order. Older peers don't send them.
+    #[serde(default)]
+    parameter_names: Vec<String>,
+    #[serde(default)]
+    parameter_descriptions: Vec<String>,
     sequences: u32,
 }
 
-#[derive(EnumString)]
-enum SequenceWithOneSub {
-    Drop,
-    Cyclic,
-    Alternating,
-    Smoothed,
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Page {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub values: Vec<Option<f64>>,
+}
+
+// How the values of a response are sent, from the query of POST /sequence/{name}.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Format {
+    pub precision: Option<u32>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Validation {
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BatchItem {
+    pub name: String,
+    pub request: SequenceRequest,
+}
+
+// Body of POST /sequence/compare. The range replaces the ranges of both requests.
+#[derive(Deserialize, Debug)]
+pub struct CompareRequest {
+    pub first: BatchItem,
+    pub second: BatchItem,
+    pub range: Range,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+}
+
+fn default_tolerance() -> f64 {
+    1e-9
+}
+
+// Parameters and sub-sequences of the sequence named in the path, for
+// requests that look at a fixed stretch of terms instead of a range.
+#[derive(Deserialize, Debug)]
+pub struct SequenceArguments {
+    pub parameters: Vec<f64>,
+    pub sequences: Vec<Box<SequenceSyntax>>,
+}
+
+// Body of POST /sequence/{name}/limit: the sequence without a range, since the
+// terms from 0 to max_iterations - 1 are the ones looked at.
+#[derive(Deserialize, Debug)]
+pub struct LimitRequest {
+    #[serde(flatten)]
+    pub sequence: SequenceArguments,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    #[serde(default = "default_max_iterations")]
+    pub max_iterations: u64,
+}
+
+fn default_max_iterations() -> u64 {
+    10_000
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Limit {
+    pub converges: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<f64>,
+    // Index from which successive differences stay below the tolerance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settled_at: Option<u64>,
+}
+
+// Body of POST /sequence/{name}/period; the terms from 0 to window - 1 are
+// searched for a period.
+#[derive(Deserialize, Debug)]
+pub struct PeriodRequest {
+    #[serde(flatten)]
+    pub sequence: SequenceArguments,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    #[serde(default = "default_window")]
+    pub window: u64,
+}
+
+fn default_window() -> u64 {
+    1_000
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Period {
+    // None if nothing repeats within the window.
+    pub period: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Comparison {
+    pub equal: bool,
+    // Sequence index (not position in the range) of the first differing element.
+    pub first_divergence: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct RequestError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl RequestError {
+    pub fn new<T: Into<String>>(status: StatusCode, message: T) -> RequestError {
+        RequestError { status, message: message.into() }
+    }
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+    // Not one of ours, so only a peer can build it.
+    UnknownSequence(String),
+    // Path is where in the tree the node is, e.g. Sum.sequences[1]. For
+    // sequences with a variable parameter count the expected one is a minimum.
+    WrongParamCount { path: String, name: String, expected: usize, at_least: bool, got: usize },
+    WrongChildCount { path: String, name: String, expected: usize, got: usize },
+    // A sequence only defined over a range below one that reads single elements.
+    RangeOnly { path: String, name: String, parent: String },
+    // Rejected by the sequence's constructor, e.g. Modulo by zero.
+    InvalidParameters(RequestError),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BuildError::UnknownSequence(name) => write!(f, "Unknown sequence {}", name),
+            BuildError::WrongParamCount { path, name, expected, at_least: true, got } => {
+                write!(f, "{}: {} expects at least {} parameters, got {}", path, name, expected, got)
+            }
+            BuildError::WrongParamCount { path, name, expected, at_least: false, got } => {
+                write!(f, "{}: {} expects {} parameters, got {}", path, name, expected, got)
+            }
+            BuildError::WrongChildCount { path, name, expected, got } => write!(f, "{}: {} expects {} sequences, got {}", path, name, expected, got),
+            BuildError::RangeOnly { path, name, parent } => {
+                write!(f, "{}: {} is only defined over a range and can't be used inside {}", path, name, parent)
+            }
+            BuildError::InvalidParameters(e) => write!(f, "{}", e.message),
+        }
+    }
+}
+
+impl From<BuildError> for RequestError {
+    fn from(e: BuildError) -> RequestError {
+        match e {
+            BuildError::InvalidParameters(e) => e,
+            BuildError::UnknownSequence(_) => RequestError::new(StatusCode::NOT_FOUND, e.to_string()),
+            _ => RequestError::new(StatusCode::BAD_REQUEST, e.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorResponse {
+    pub code: u16,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Health {
+    pub uptime_secs: u64,
+    pub registered: bool,
+    pub port: u16,
+    pub peers: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RegistryPing {
+    pub reachable: bool,
+    pub latency_ms: Option<u64>,
+}
+
+// What GET /capabilities advertises, so clients can adapt their requests.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Capabilities {
+    pub content_types: Vec<String>,
+    pub encodings: Vec<String>,
+    pub streaming: bool,
+    pub http2: bool,
+    pub delegation: bool,
+    pub max_range_len: u64,
+    pub body_limit: usize,
+    pub max_depth: usize,
+    pub max_evaluations: usize,
+    pub evaluation_timeout_secs: u64,
+    pub max_batch_items: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Version {
+    pub version: String,
+    pub commit: Option<String>,
+    pub target: String,
+}
+
+#[derive(Debug)]
+pub struct NodeStatus {
+    pub started: Instant,
+    pub registered: bool,
+    // Set by the first successful registration and never cleared.
+    pub ever_registered: bool,
+    pub peers: usize,
+    pub peer_sequences: Option<(Instant, BTreeMap<String, Vec<String>>)>,
+    // Round trip to /ping of each "ip:port", and when it was measured.
+    pub peer_latencies: BTreeMap<String, (Instant, Duration)>,
+}
+
+pub struct State {
+    pub config: Config,
+    pub status: Mutex<NodeStatus>,
+    pub evaluations: Arc<Semaphore>,
+    pub rate_limits: Mutex<RateLimits>,
+}
+
+pub struct RateLimits {
+    buckets: BTreeMap<IpAddr, TokenBucket>,
+    // When buckets that have filled up again were last dropped.
+    swept: Instant,
+}
+
+// Holds up to rate_burst tokens and gains rate_limit of them per second; each
+// request takes one.
+pub struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl State {
+    pub fn new(config: Config) -> State {
+        State {
+            evaluations: Arc::new(Semaphore::new(config.max_evaluations)),
+            rate_limits: Mutex::new(RateLimits { buckets: BTreeMap::new(), swept: Instant::now() }),
+            config,
+            status: Mutex::new(NodeStatus {
+                started: Instant::now(),
+                registered: false,
+                ever_registered: false,
+                peers: 0,
+                peer_sequences: None,
+                peer_latencies: BTreeMap::new(),
+            }),
+        }
+    }
+
+    // Ok if the client may make another request, otherwise the time until it may.
+    fn take_token(&self, ip: IpAddr) -> Result<(), Duration> {
+        let (rate, burst) = (self.config.rate_limit, self.config.rate_burst);
+        if rate <= 0.0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut limits = self.rate_limits.lock().unwrap();
+        // A bucket that has filled up again is the same as a new one. Looking
+        // at every bucket is slow with many clients, so it happens only now
+        // and then.
+        if now.duration_since(limits.swept) >= Duration::from_secs(RATE_SWEEP_SECS) {
+            limits.buckets.retain(|_, bucket| bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst);
+            limits.swept = now;
+        }
+        let bucket = limits.buckets.entry(ip).or_insert(TokenBucket { tokens: burst, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+// Requests of one connection, so it can be closed once it has had none for
+// connection_timeout.
+#[derive(Clone)]
+struct IdleTimer {
+    timeout: Duration,
+    activity: Arc<Mutex<Activity>>,
+}
+
+struct Activity {
+    in_flight: usize,
+    last: Instant,
+}
+
+// Marks a request as being answered until dropped.
+struct Busy(IdleTimer);
+
+impl IdleTimer {
+    fn new(timeout: Duration) -> IdleTimer {
+        IdleTimer { timeout, activity: Arc::new(Mutex::new(Activity { in_flight: 0, last: Instant::now() })) }
+    }
+
+    fn busy(&self) -> Busy {
+        self.activity.lock().unwrap().in_flight += 1;
+        Busy(self.clone())
+    }
+
+    // Completes once no request has been in flight for the whole timeout.
+    async fn expired(&self) {
+        loop {
+            let deadline = {
+                let activity = self.activity.lock().unwrap();
+                if activity.in_flight == 0 && activity.last.elapsed() >= self.timeout {
+                    return;
+                }
+                if activity.in_flight == 0 { activity.last + self.timeout } else { Instant::now() + self.timeout }
+            };
+            tokio::time::sleep_until(deadline.into()).await;
+        }
+    }
+}
+
+impl std::ops::Drop for Busy {
+    fn drop(&mut self) {
+        let mut activity = self.0.activity.lock().unwrap();
+        activity.in_flight -= 1;
+        activity.last = Instant::now();
+    }
+}
+
+type Shared = Arc<State>;
+
+const RESPONSE_SIZE_BUCKETS: [u64; 5] = [100, 1_000, 10_000, 100_000, 1_000_000];
+
+pub struct Metrics {
+    requests: AtomicU64,
+    requests_by_sequence: Mutex<BTreeMap<&'static str, u64>>,
+    delegations: AtomicU64,
+    delegation_failures: AtomicU64,
+    response_size_buckets: [AtomicU64; RESPONSE_SIZE_BUCKETS.len()],
+    response_size_sum: AtomicU64,
+    response_size_count: AtomicU64,
+}
+
+static METRICS: Metrics = Metrics {
+    requests: AtomicU64::new(0),
+    requests_by_sequence: Mutex::new(BTreeMap::new()),
+    delegations: AtomicU64::new(0),
+    delegation_failures: AtomicU64::new(0),
+    response_size_buckets: [const { AtomicU64::new(0) }; RESPONSE_SIZE_BUCKETS.len()],
+    response_size_sum: AtomicU64::new(0),
+    response_size_count: AtomicU64::new(0),
+};
+
+impl Metrics {
+    // Names come from clients, so any we don't have share one label instead of
+    // each getting its own series.
+    fn count_request(&self, name: &str) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        let label = REGISTRY.iter().find(|entry| entry.name == name).map_or("other", |entry| entry.name);
+        *self.requests_by_sequence.lock().unwrap().entry(label).or_insert(0) += 1;
+    }
+
+    fn observe_response_size(&self, size: usize) {
+        let size = size as u64;
+        for (bucket, bound) in self.response_size_buckets.iter().zip(RESPONSE_SIZE_BUCKETS) {
+            if size <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.response_size_sum.fetch_add(size, Ordering::Relaxed);
+        self.response_size_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Prometheus text exposition format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let counter = |out: &mut String, name: &str, help: &str, value: &AtomicU64| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(out, "{} {}", name, value.load(Ordering::Relaxed)).unwrap();
+        };
+        counter(&mut out, "generator_requests_total", "Sequence requests evaluated.", &self.requests);
+        counter(&mut out, "generator_delegations_total", "Requests forwarded to a peer.", &self.delegations);
+        counter(&mut out, "generator_delegation_failures_total", "Requests forwarded to a peer that failed.", &self.delegation_failures);
+
+        writeln!(out, "# HELP generator_sequence_requests_total Sequence requests by sequence name.").unwrap();
+        writeln!(out, "# TYPE generator_sequence_requests_total counter").unwrap();
+        for (name, count) in self.requests_by_sequence.lock().unwrap().iter() {
+            let name = name.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            writeln!(out, "generator_sequence_requests_total{{sequence=\"{}\"}} {}", name, count).unwrap();
+        }
+
+        writeln!(out, "# HELP generator_response_size_bytes Size of sequence responses.").unwrap();
+        writeln!(out, "# TYPE generator_response_size_bytes histogram").unwrap();
+        for (bucket, bound) in self.response_size_buckets.iter().zip(RESPONSE_SIZE_BUCKETS) {
+            writeln!(out, "generator_response_size_bytes_bucket{{le=\"{}\"}} {}", bound, bucket.load(Ordering::Relaxed)).unwrap();
+        }
+        let count = self.response_size_count.load(Ordering::Relaxed);
+        writeln!(out, "generator_response_size_bytes_bucket{{le=\"+Inf\"}} {}", count).unwrap();
+        writeln!(out, "generator_response_size_bytes_sum {}", self.response_size_sum.load(Ordering::Relaxed)).unwrap();
+        writeln!(out, "generator_response_size_bytes_count {}", count).unwrap();
+        out
+    }
+}
+
+thread_local! {
+    // Flag of the computation run_blocking is running on this thread, set
+    // once it has timed out.
+    static CANCELLED: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
 }
 
-#[derive(EnumString)]
-enum SequenceWithTwoSubs {
-    Sum,
-    Prod,
-    LinComb,
-    Average,
+// Whether the current computation has timed out. Nobody waits for its result
+// any more, so loops that can run long stop early and return whatever they have.
+fn cancelled() -> bool {
+    CANCELLED.with(|flag| flag.borrow().as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed)))
 }
 
 pub trait Sequence: Send + Sync {
     fn k_th(&self, k: usize) -> f64 {
         self.range(Range {from: k as u64, to: k as u64, step: 1})[0]
     }
+    // Sequences defined only from index 0 on keep this default, which gives
+    // NaN (sent as null) at negative indices.
+    fn k_th_signed(&self, k: i64) -> f64 {
+        if k < 0 {
+            f64::NAN
+        } else {
+            self.k_th(k as usize)
+        }
+    }
+    // Negative indices go through k_th_signed and the rest through range,
+    // so sequences that override range keep working.
+    fn range_signed(&self, range: SignedRange) -> Vec<f64> {
+        let mut result = Vec::new();
+        // Larger steps are rejected with the request.
+        let step = range.step.min(i64::MAX as u64) as i64;
+        let mut k = range.from;
+        while k < 0 && k <= range.to && !cancelled() {
+            result.push(self.k_th_signed(k));
+            match k.checked_add(step) {
+                Some(next) => k = next,
+                None => return result,
+            }
+        }
+        if k <= range.to {
+            result.extend(self.range(Range { from: k as u64, to: range.to as u64, step: range.step }));
+        }
+        result
+    }
     fn range(&self, range: Range) -> Vec<f64> {
         let mut result = Vec::new();
         let mut k = range.from;
-        while k <= range.to {
+        while k <= range.to && !cancelled() {
             result.push(self.k_th(k as usize));
-            k += range.step;
+            match k.checked_add(range.step) {
+                Some(next) => k = next,
+                None => break,
+            }
         }
         result
     }
@@ -100,6 +780,10 @@ impl Sequence for Arithmetic {
     fn k_th(&self, k: usize) -> f64 {
         self.start + (k as f64) * self.step
     }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        self.start + (k as f64) * self.step
+    }
 }
 
 pub struct Geometric {
@@ -117,6 +801,10 @@ impl Sequence for Geometric {
     fn k_th(&self, k: usize) -> f64 {
         self.start * f64::powf(self.factor, k as f64)
     }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        self.start * f64::powf(self.factor, k as f64)
+    }
 }
 
 pub struct Constant {
@@ -133,6 +821,10 @@ impl Sequence for Constant {
     fn k_th(&self, _k: usize) -> f64 {
         self.start
     }
+
+    fn k_th_signed(&self, _k: i64) -> f64 {
+        self.start
+    }
 }
 
 pub struct Sum {
@@ -150,6 +842,10 @@ impl Sequence for Sum {
     fn k_th(&self, k: usize) -> f64 {
         self.seq1.k_th(k) + self.seq2.k_th(k)
     }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        self.seq1.k_th_signed(k) + self.seq2.k_th_signed(k)
+    }
 }
 
 pub struct Prod {
@@ -167,6 +863,10 @@ impl Sequence for Prod {
     fn k_th(&self, k: usize) -> f64 {
         self.seq1.k_th(k) * self.seq2.k_th(k)
     }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        self.seq1.k_th_signed(k) * self.seq2.k_th_signed(k)
+    }
 }
 
 pub struct Drop {
@@ -180,9 +880,20 @@ impl Drop {
     }
 }
 
+// Indices past the end of usize (or i64) have no element to read, so they give NaN.
 impl Sequence for Drop {
     fn k_th(&self, k: usize) -> f64 {
-        self.seq.k_th(k + self.n)
+        match k.checked_add(self.n) {
+            Some(k) => self.seq.k_th(k),
+            None => f64::NAN,
+        }
+    }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        match i64::try_from(self.n).ok().and_then(|n| k.checked_add(n)) {
+            Some(k) => self.seq.k_th_signed(k),
+            None => f64::NAN,
+        }
     }
 }
 
@@ -204,6 +915,10 @@ impl Sequence for LinComb {
     fn k_th(&self, k: usize) -> f64 {
         self.a * self.seq1.k_th(k) + self.b * self.seq2.k_th(k) + self.c
     }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        self.a * self.seq1.k_th_signed(k) + self.b * self.seq2.k_th_signed(k) + self.c
+    }
 }
 
 pub struct Recursive {
@@ -222,24 +937,109 @@ impl Recursive {
 impl Sequence for Recursive {
     fn range(&self, range: Range) -> Vec<f64> {
         let mut result = Vec::new();
-        if range.from == 0 {
-            result.push(self.x0);
-        }
-        if range.from <= 1 && range.to >= 1 {
-            result.push(self.x1);
-        }
-        let mut x_n_minus_2 = self.x0;
-        let mut x_n_minus_1 = self.x1;
-        for k in 2..=range.to {
-            let x_n = self.a * x_n_minus_1 + self.b * x_n_minus_2;
-            if k >= range.from {
-                result.push(x_n);
-            }
-            x_n_minus_2 = x_n_minus_1;
-            x_n_minus_1 = x_n;
-            if k + 1 > range.to {
-                break;
+        let mut x_k = self.x0;
+        let mut x_k_plus_1 = self.x1;
+        for k in (0..=range.to).take_while(|_| !cancelled()) {
+            if range.contains(k) {
+                result.push(x_k);
+            }
+            let x_k_plus_2 = self.a * x_k_plus_1 + self.b * x_k;
+            x_k = x_k_plus_1;
+            x_k_plus_1 = x_k_plus_2;
+        }
+        result
+    }
+}
+
+pub struct Binomial {
+    n: usize
+}
+
+impl Binomial {
+    pub fn new(n: usize) -> Box<Binomial> {
+        Box::new(Binomial { n })
+    }
+}
+
+impl Sequence for Binomial {
+    fn k_th(&self, k: usize) -> f64 {
+        if k > self.n {
+            return 0.0;
+        }
+        // C(n, k) = C(n, n - k), and multiplying one factor at a time keeps it from overflowing early.
+        (1..=k.min(self.n - k)).take_while(|_| !cancelled()).fold(1.0, |c, i| c * (self.n - i + 1) as f64 / i as f64)
+    }
+}
+
+pub struct Padovan;
+
+impl Padovan {
+    pub fn new() -> Box<Padovan> {
+        Box::new(Padovan)
+    }
+}
+
+impl Sequence for Padovan {
+    fn range(&self, range: Range) -> Vec<f64> {
+        let mut result = Vec::new();
+        let (mut p_k, mut p_k_plus_1, mut p_k_plus_2) = (1.0, 1.0, 1.0);
+        for k in (0..=range.to).take_while(|_| !cancelled()) {
+            if range.contains(k) {
+                result.push(p_k);
+            }
+            let p_k_plus_3 = p_k_plus_1 + p_k;
+            p_k = p_k_plus_1;
+            p_k_plus_1 = p_k_plus_2;
+            p_k_plus_2 = p_k_plus_3;
+        }
+        result
+    }
+}
+
+// Catalan numbers 1, 1, 2, 5, 14, ... As f64 they are exact only up to
+// k = 30; after that they are rounded, and from k = 515 on they overflow.
+pub struct Catalan;
+
+impl Catalan {
+    pub fn new() -> Box<Catalan> {
+        Box::new(Catalan)
+    }
+}
+
+impl Sequence for Catalan {
+    fn range(&self, range: Range) -> Vec<f64> {
+        let mut result = Vec::new();
+        let mut c_k = 1.0;
+        for k in (0..=range.to).take_while(|_| !cancelled()) {
+            if range.contains(k) {
+                result.push(c_k);
+            }
+            c_k = c_k * 2.0 * (2 * k + 1) as f64 / (k + 2) as f64;
+        }
+        result
+    }
+}
+
+pub struct Logistic {
+    r: f64,
+    x0: f64
+}
+
+impl Logistic {
+    pub fn new(r: f64, x0: f64) -> Box<Logistic> {
+        Box::new(Logistic { r, x0 })
+    }
+}
+
+impl Sequence for Logistic {
+    fn range(&self, range: Range) -> Vec<f64> {
+        let mut result = Vec::new();
+        let mut x_k = self.x0;
+        for k in (0..=range.to).take_while(|_| !cancelled()) {
+            if range.contains(k) {
+                result.push(x_k);
             }
+            x_k = self.r * x_k * (1.0 - x_k);
         }
         result
     }
@@ -260,6 +1060,10 @@ impl Sequence for Average {
     fn k_th(&self, k: usize) -> f64 {
         (self.seq1.k_th(k) + self.seq2.k_th(k)) / 2.0
     }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        (self.seq1.k_th_signed(k) + self.seq2.k_th_signed(k)) / 2.0
+    }
 }
 
 pub struct Cyclic {
@@ -291,10 +1095,16 @@ impl Alternating {
 
 impl Sequence for Alternating {
     fn k_th(&self, k: usize) -> f64 {
-        (-1.0f64).powi(k.try_into().unwrap()) * self.seq.k_th(k)
+        let sign = if k.is_multiple_of(2) { 1.0 } else { -1.0 };
+        sign * self.seq.k_th(k)
     }
 }
 
+// Every element averages itself with its neighbours in the underlying
+// sequence, not in the requested range: the first element of
+// `range(2..4)` still reads index 1, so a range gives exactly the same
+// values as calling `k_th` on each index. Only index 0, which has no left
+// neighbour, averages two elements instead of three.
 pub struct Smoothed {
     seq: Box<dyn Sequence>
 }
@@ -307,7 +1117,9 @@ impl Smoothed {
 
 impl Sequence for Smoothed {
     fn k_th(&self, k: usize) -> f64 {
-        if k == 0 {
+        if k == usize::MAX {
+            f64::NAN
+        } else if k == 0 {
             (self.seq.k_th(k) + self.seq.k_th(k+1)) / 2.0
         } else {
             (self.seq.k_th(k-1) + self.seq.k_th(k) + self.seq.k_th(k+1)) / 3.0
@@ -315,324 +1127,5338 @@ impl Sequence for Smoothed {
     }
 }
 
-fn sequences() -> Vec<SequenceInfo> {
-    let mut sequences = Vec::new();
-    sequences.push(SequenceInfo {
-        name: "Arithmetic".to_string(),
-        description: "Arithmetic sequence".to_string(),
-        parameters: 2,
-        sequences: 0,
-    });
-    sequences.push(SequenceInfo {
-        name: "Geometric".to_string(),
-        description: "Geometric sequence".to_string(),
-        parameters: 2,
-        sequences: 0,
-    });
-    sequences.push(SequenceInfo {
-        name: "Constant".to_string(),
-        description: "Constant sequence".to_string(),
-        parameters: 1,
-        sequences: 0,
-    });
-    sequences.push(SequenceInfo {
-        name: "Sum".to_string(),
-        description: "Element-wise sum of two sequences".to_string(),
-        parameters: 0,
-        sequences: 2,
-    });
-    sequences.push(SequenceInfo {
-        name: "Prod".to_string(),
-        description: "Element-wise product of two sequences".to_string(),
-        parameters: 0,
-        sequences: 2,
-    });
-    sequences.push(SequenceInfo {
-        name: "Drop".to_string(),
-        description: "Drops the first n elements of a sequence".to_string(),
-        parameters: 1,
-        sequences: 1,
-    });
-    sequences.push(SequenceInfo {
-        name: "LinComb".to_string(),
-        description: "Linear combination of two sequences".to_string(),
-        parameters: 3,
-        sequences: 2,
-    });
-    sequences.push(SequenceInfo {
-        name: "Recursive".to_string(),
-        description: "Recursive sequence of the form x(n) = ax(n-1) + bx(n-2)".to_string(),
-        parameters: 4,
-        sequences: 0,
-    });
-    sequences.push(SequenceInfo {
-        name: "Average".to_string(),
-        description: "Element-wise average of two given sequences".to_string(),
-        parameters: 0,
-        sequences: 2,
-    });
-    sequences.push(SequenceInfo {
-        name: "Cyclic".to_string(),
-        description: "Sequence that cycles over a specified number of starting terms of another sequence".to_string(),
-        parameters: 1,
-        sequences: 1,
-    });
-    sequences.push(SequenceInfo {
-        name: "Alternating".to_string(),
-        description: "Sequence where the sign of each element alternates based on its index.".to_string(),
-        parameters: 0,
-        sequences: 1,
-    });
-    sequences.push(SequenceInfo {
-        name: "Smoothed".to_string(),
-        description: "Sequence where each element is the average of itself and its immediate neighbors".to_string(),
-        parameters: 0,
-        sequences: 1,
-    });
-    sequences
+// Only defined over a finite range: `range` evaluates the underlying
+// sequence and returns the values in reverse order. A single `k_th` has no
+// meaning on its own, so calling it panics.
+pub struct Reverse {
+    seq: Box<dyn Sequence>
 }
 
-fn get_project() -> Project {
-    return Project {
-        name: "Lucija & Lev".to_string(),
-        ip: "127.0.0.1".to_string(),
-        port: PORT,
-    };
+impl Reverse {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<Reverse> {
+        Box::new(Reverse { seq })
+    }
 }
 
-fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
-    Full::new(chunk.into())
-        .map_err(|never| match never {})
-        .boxed()
+impl Sequence for Reverse {
+    fn k_th(&self, _k: usize) -> f64 {
+        panic!("Reverse is only defined over a range")
+    }
+    fn range(&self, range: Range) -> Vec<f64> {
+        let mut result = self.seq.range(range);
+        result.reverse();
+        result
+    }
+}
+
+pub struct Normalize {
+    seq: Box<dyn Sequence>
 }
-async fn collect_body(req: Request<Incoming>) -> Result<String, hyper::Error> {
-    let max = req.body().size_hint().upper().unwrap_or(u64::MAX);
-    if max > 1024 * 64 {
-        panic!("Body too big");
+
+impl Normalize {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<Normalize> {
+        Box::new(Normalize { seq })
     }
+}
 
-    let whole_body = req.collect().await?.to_bytes();
-    let whole_body = std::str::from_utf8(&whole_body).unwrap().to_string();
-    return Ok(whole_body);
+impl Sequence for Normalize {
+    fn k_th(&self, _k: usize) -> f64 {
+        panic!("Normalize is only defined over a range")
+    }
+    fn range(&self, range: Range) -> Vec<f64> {
+        let values = self.seq.range(range);
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        if min == max {
+            return vec![0.0; values.len()];
+        }
+        values.into_iter().map(|x| (x - min) / (max - min)).collect()
+    }
 }
 
-fn empty() -> BoxBody<Bytes, hyper::Error> {
-    Empty::<Bytes>::new()
-        .map_err(|never| match never {})
-        .boxed()
+pub struct PeakNormalize {
+    seq: Box<dyn Sequence>
 }
 
-async fn send_post(url: String, body: String) -> Result<String, reqwest::Error> {
-    let client = reqwest::Client::new();
-    let res = client.post(url).body(body).send().await?.text().await?;
-    return Ok(res);
+impl PeakNormalize {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<PeakNormalize> {
+        Box::new(PeakNormalize { seq })
+    }
 }
 
-async fn send_get(url: String) -> Result<String, reqwest::Error> {
-    let client = reqwest::Client::new();
-    let res = client.get(url).send().await?.text().await?;
-    return Ok(res);
+impl Sequence for PeakNormalize {
+    fn k_th(&self, _k: usize) -> f64 {
+        panic!("PeakNormalize is only defined over a range")
+    }
+    fn range(&self, range: Range) -> Vec<f64> {
+        let values = self.seq.range(range);
+        let peak = values.iter().fold(0.0, |peak: f64, x| peak.max(x.abs()));
+        if peak == 0.0 {
+            return vec![0.0; values.len()];
+        }
+        values.into_iter().map(|x| x / peak).collect()
+    }
 }
 
-async fn handle_sequence_request(req: Request<Incoming>, sequence_info: &SequenceInfo) -> Result<Response<BoxBody<Bytes, Error>>, hyper::Error> {
-    let body = collect_body(req).await?;
-    println!("{:?}", body);
-    let request: SequenceRequest = serde_json::from_str(&body).unwrap();
-    let request1: SequenceRequest = serde_json::from_str(&body).unwrap();
-    let range = request.range;
+pub struct Interleave {
+    seq1: Box<dyn Sequence>,
+    seq2: Box<dyn Sequence>
+}
 
-    let seqs = sequences();
-    let mut name_of_seq: &str = &sequence_info.name;
+impl Interleave {
+    pub fn new(seq1: Box<dyn Sequence>, seq2: Box<dyn Sequence>) -> Box<Interleave> {
+        Box::new(Interleave { seq1, seq2 })
+    }
+}
 
-    for seq in request.sequences.iter() {
-        let seq_name = seq.name.clone();
-        if seqs.iter().find(|&x| x.name == seq_name).is_some() {
-                name_of_seq = &sequence_info.name;
-            }
-        else {
-            name_of_seq = "";
-            break;
+impl Sequence for Interleave {
+    fn k_th(&self, k: usize) -> f64 {
+        if k.is_multiple_of(2) {
+            self.seq1.k_th(k / 2)
+        } else {
+            self.seq2.k_th(k / 2)
         }
     }
+}
 
-    let sequence: Option<Box<dyn Sequence>> = match name_of_seq {
-        without_seq if ["Arithmetic", "Geometric", "Constant", "Recursive"].contains(&without_seq)
-            => Some(create_sequence_from_syntax(&name_of_seq, &request.parameters)),
-        with_one if ["Drop", "Cyclic", "Alternating", "Smoothed"].contains(&with_one) => {
-            let seq = create_sequence_from_syntax(&request.sequences[0].name, &request.sequences[0].parameters);
-            match SequenceWithOneSub::from_str(with_one).unwrap() {
-                SequenceWithOneSub::Drop => Some(Drop::new(seq, request.parameters[0] as usize)),
-                SequenceWithOneSub::Cyclic => Some(Cyclic::new(seq, request.parameters[0] as usize)),
-                SequenceWithOneSub::Alternating => Some(Alternating::new(seq)),
-                SequenceWithOneSub::Smoothed => Some(Smoothed::new(seq)),
-            }
-        }
-        with_two if ["Sum", "Prod", "LinComb", "Average"].contains(&with_two) => {
-            let seq1 = create_sequence_from_syntax(&request.sequences[0].name, &request.sequences[0].parameters);
-            let seq2 = create_sequence_from_syntax(&request.sequences[1].name, &request.sequences[1].parameters);
-            match SequenceWithTwoSubs::from_str(with_two).unwrap() {
-                SequenceWithTwoSubs::Sum => Some(Sum::new(seq1, seq2)),
-                SequenceWithTwoSubs::Prod => Some(Prod::new(seq1, seq2)),
-                SequenceWithTwoSubs::LinComb => Some(LinComb::new(request.parameters[0], request.parameters[1], request.parameters[2], seq1, seq2)),
-                SequenceWithTwoSubs::Average => Some(Average::new(seq1, seq2))
-            }
+pub struct Clamp {
+    lo: f64,
+    hi: f64,
+    seq: Box<dyn Sequence>
+}
+
+impl Clamp {
+    pub fn new(lo: f64, hi: f64, seq: Box<dyn Sequence>) -> Box<Clamp> {
+        Box::new(Clamp { lo, hi, seq })
+    }
+}
+
+impl Sequence for Clamp {
+    fn k_th(&self, k: usize) -> f64 {
+        // f64::clamp panics when lo > hi, which a client can easily send.
+        self.seq.k_th(k).max(self.lo).min(self.hi)
+    }
+}
+
+pub struct ClampBetween {
+    seq: Box<dyn Sequence>,
+    lo: Box<dyn Sequence>,
+    hi: Box<dyn Sequence>
+}
+
+impl ClampBetween {
+    pub fn new(seq: Box<dyn Sequence>, lo: Box<dyn Sequence>, hi: Box<dyn Sequence>) -> Box<ClampBetween> {
+        Box::new(ClampBetween { seq, lo, hi })
+    }
+}
+
+impl Sequence for ClampBetween {
+    fn k_th(&self, k: usize) -> f64 {
+        // Same order as Clamp, so crossed bounds give the upper one.
+        self.seq.k_th(k).max(self.lo.k_th(k)).min(self.hi.k_th(k))
+    }
+}
+
+pub struct Abs {
+    seq: Box<dyn Sequence>
+}
+
+impl Abs {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<Abs> {
+        Box::new(Abs { seq })
+    }
+}
+
+impl Sequence for Abs {
+    fn k_th(&self, k: usize) -> f64 {
+        self.seq.k_th(k).abs()
+    }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        self.seq.k_th_signed(k).abs()
+    }
+}
+
+pub struct Negate {
+    seq: Box<dyn Sequence>
+}
+
+impl Negate {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<Negate> {
+        Box::new(Negate { seq })
+    }
+}
+
+impl Sequence for Negate {
+    fn k_th(&self, k: usize) -> f64 {
+        -self.seq.k_th(k)
+    }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        -self.seq.k_th_signed(k)
+    }
+}
+
+// Ratio of the next element to the current one. Where a sequence element is 0
+// the ratio is infinite or NaN, which serde_json sends as null like any
+// other non-finite output.
+pub struct GrowthRatio {
+    seq: Box<dyn Sequence>
+}
+
+impl GrowthRatio {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<GrowthRatio> {
+        Box::new(GrowthRatio { seq })
+    }
+}
+
+impl Sequence for GrowthRatio {
+    fn k_th(&self, k: usize) -> f64 {
+        match k.checked_add(1) {
+            Some(next) => self.seq.k_th(next) / self.seq.k_th(k),
+            None => f64::NAN,
         }
-        _ => None,
-    };
+    }
+}
 
-    match sequence {
-        Some(seq) => {
-            let result = serde_json::to_string(&seq.range(range)).unwrap();
-            println!("{}", &result);
-            Ok(Response::new(full(result)))
-        },
-        None => {
-            let result = match delegate(request1, &sequence_info.name).await {
-                Ok(resp) => resp,
-                Err(e) => {
-                    eprintln!("Failed to delegate request: {}", e);
-                    return Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(empty())
-                        .unwrap());
-                }
-            };
-            println!("{:?}", result.clone());
-            Ok(Response::new(full(result)))
-        },
+pub struct Floor {
+    seq: Box<dyn Sequence>
+}
+
+impl Floor {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<Floor> {
+        Box::new(Floor { seq })
     }
 }
 
-fn create_sequence_from_syntax(name: &str, parameters: &Vec<f64>) -> Box<dyn Sequence> {
-    match name {
-        "Arithmetic" => Arithmetic::new(parameters[0], parameters[1]),
-        "Geometric" => Geometric::new(parameters[0], parameters[1]),
-        "Constant" => Constant::new(parameters[0]),
-        "Recursive" => Recursive::new(parameters[0], parameters[1], parameters[2], parameters[3]),
-        _ => panic!("Unsupported sequence")
+impl Sequence for Floor {
+    fn k_th(&self, k: usize) -> f64 {
+        self.seq.k_th(k).floor()
     }
 }
 
-async fn delegate(request: SequenceRequest, sequence_name: &str) -> Result<String, hyper::Error> {
-    
-    let b = match send_get("http://127.0.0.1:7878/project".to_string()).await {
-        Ok(resp) => resp,
-        Err(e) => panic!("{}", e),
-    };
-    let projects: serde_json::Value = serde_json::from_str(&b).unwrap();
-    let length = projects.as_array().unwrap().len();
+pub struct Ceil {
+    seq: Box<dyn Sequence>
+}
 
-    let body = match serde_json::to_string(&request) {
-        Ok(req) => req,
-        Err(e) => panic!("{}", e),
-    };
+impl Ceil {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<Ceil> {
+        Box::new(Ceil { seq })
+    }
+}
 
-    if length > 0 {
-        let mut vec: Vec<usize> = (0..length).collect();
-        vec.shuffle(&mut thread_rng());
+impl Sequence for Ceil {
+    fn k_th(&self, k: usize) -> f64 {
+        self.seq.k_th(k).ceil()
+    }
+}
 
-        for i in vec.iter() {
-            let ip = &projects[i]["ip"].to_string().replace("\"", "");
-            let port = &projects[i]["port"];
-            println!("ip:port = {}:{}", &ip, &port);
+pub struct Round {
+    seq: Box<dyn Sequence>
+}
 
-            if ip != "127.0.0.1" || port != PORT {
-                let url = format!("http://{}:{}/sequence", ip, port);
-                let data = match send_get(url.clone()).await {
-                    Ok(resp) => resp,
-                    Err(e) => panic!("{}", e),
-                };
+impl Round {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<Round> {
+        Box::new(Round { seq })
+    }
+}
 
-                let seqs: serde_json::Value = serde_json::from_str(&data).unwrap();
-                for i in 0..seqs.as_array().unwrap().len() {
-                    if format!("/sequence/{}", seqs[i]["name"].to_string().replace("\"", "")) == sequence_name {
-                        let url_of_seq = format!("{}/{}", &url, seqs[i]["name"].to_string().replace("\"", ""));
-                        return match send_post(url_of_seq, body).await {
-                            Ok(resp) => Ok(resp),
-                            Err(e) => {
-                                eprintln!("{}", e);
-                                Ok("[]".to_string())
-                            },
-                        };
-                    };
-                }
-            }
+impl Sequence for Round {
+    fn k_th(&self, k: usize) -> f64 {
+        self.seq.k_th(k).round()
+    }
+}
+
+pub struct Modulo {
+    m: f64,
+    seq: Box<dyn Sequence>
+}
+
+impl Modulo {
+    pub fn new(m: f64, seq: Box<dyn Sequence>) -> Box<Modulo> {
+        Box::new(Modulo { m, seq })
+    }
+}
+
+impl Sequence for Modulo {
+    fn k_th(&self, k: usize) -> f64 {
+        self.seq.k_th(k).rem_euclid(self.m)
+    }
+}
+
+pub struct MovingAverage {
+    window: usize,
+    seq: Box<dyn Sequence>
+}
+
+impl MovingAverage {
+    pub fn new(window: usize, seq: Box<dyn Sequence>) -> Box<MovingAverage> {
+        Box::new(MovingAverage { window, seq })
+    }
+}
+
+impl Sequence for MovingAverage {
+    fn k_th(&self, k: usize) -> f64 {
+        let half = self.window / 2;
+        // Like Smoothed at k == 0, the window is cut off at index 0 and only
+        // the elements that exist are averaged.
+        let from = k.saturating_sub(half);
+        let to = k.saturating_add(half);
+        let sum: f64 = (from..=to).take_while(|_| !cancelled()).map(|i| self.seq.k_th(i)).sum();
+        sum / (to - from + 1) as f64
+    }
+}
+
+pub struct Concat {
+    n: usize,
+    seq1: Box<dyn Sequence>,
+    seq2: Box<dyn Sequence>
+}
+
+impl Concat {
+    pub fn new(n: usize, seq1: Box<dyn Sequence>, seq2: Box<dyn Sequence>) -> Box<Concat> {
+        Box::new(Concat { n, seq1, seq2 })
+    }
+}
+
+impl Sequence for Concat {
+    fn k_th(&self, k: usize) -> f64 {
+        if k < self.n {
+            self.seq1.k_th(k)
+        } else {
+            self.seq2.k_th(k - self.n)
         }
-        eprintln!("Nobody has {}", sequence_name);
-        Ok("[]".to_string())
-    } else {
-        eprintln!("Nobody has {}", sequence_name);
-        Ok("[]".to_string())
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr: SocketAddr = ([127, 0, 0, 1], PORT).into();
+pub struct Piecewise {
+    n: usize,
+    seq1: Box<dyn Sequence>,
+    seq2: Box<dyn Sequence>
+}
 
-    let b = send_get("http://127.0.0.1:7878/project".to_string()).await?;
-    println!("HERE GET {}", b);
+impl Piecewise {
+    pub fn new(n: usize, seq1: Box<dyn Sequence>, seq2: Box<dyn Sequence>) -> Box<Piecewise> {
+        Box::new(Piecewise { n, seq1, seq2 })
+    }
+}
 
-    let b = send_post(
-        "http://127.0.0.1:7878/project".to_string(),
-        serde_json::to_string(&get_project()).unwrap(),
-    ).await?;    
-    println!("HERE POST {}", b);
+impl Sequence for Piecewise {
+    fn k_th(&self, k: usize) -> f64 {
+        if k < self.n {
+            self.seq1.k_th(k)
+        } else {
+            self.seq2.k_th(k)
+        }
+    }
+}
 
-    let b = send_get("http://127.0.0.1:7878/project".to_string()).await?;
-    println!("HERE GET {}", b);
+pub struct Repeat {
+    block: Vec<f64>
+}
 
-    let listener = TcpListener::bind(addr).await?;
-    println!("Listening on http://{}", addr);
+impl Repeat {
+    pub fn new(block: Vec<f64>) -> Box<Repeat> {
+        Box::new(Repeat { block })
+    }
+}
 
-    let create_404 = || {
-        let mut not_found = Response::new(empty());
-        *not_found.status_mut() = StatusCode::NOT_FOUND;
-        Ok(not_found)
-    };
+impl Sequence for Repeat {
+    fn k_th(&self, k: usize) -> f64 {
+        self.block[k % self.block.len()]
+    }
+}
 
-    loop {
-        let (stream, _) = listener.accept().await?;
-        let io = TokioIo::new(stream);
-
-        tokio::task::spawn(async move {
-            let service = service_fn(move |req| {
-                async move {
-                    match (req.method(), req.uri().path()) {
-                        (&Method::GET, "/ping") => Ok::<_, Error>(Response::new(full(
-                            serde_json::to_string(&get_project()).unwrap(),
-                        ))),
-                        (&Method::GET, "/sequence") => {
-                            let sequences = sequences();
-                            let data = serde_json::to_string(&sequences).unwrap();
-                            Ok(Response::new(full(data,
-                        )))
-                        }
-                        (&Method::POST, r) => {
-                            let seqs = sequences();
-                            if let Some(sequence_info) = seqs
-                                .iter()
-                                .find(|&x| ("/sequence/".to_string() + &x.name) == r)
-                            {
-                                handle_sequence_request(req, sequence_info).await
-                            } else {
-                                let sequence_info = SequenceInfo {
-                                    name: r.to_string(),
-                                    description: "".to_string(),
-                                    parameters: 0,
-                                    sequences: 0,
-                                };
-                                handle_sequence_request(req, &sequence_info).await
-                            }
-                        }
-                        _ => create_404(),
-                    }
-                }
-        });
+// The values given as parameters, followed by zeros once they run out, so
+// combinators that look ahead (like `Smoothed`) still work near the end.
+pub struct Literal {
+    values: Vec<f64>
+}
+
+impl Literal {
+    pub fn new(values: Vec<f64>) -> Box<Literal> {
+        Box::new(Literal { values })
+    }
+}
+
+impl Sequence for Literal {
+    fn k_th(&self, k: usize) -> f64 {
+        self.values.get(k).copied().unwrap_or(0.0)
+    }
+}
+
+pub struct Subsample {
+    stride: usize,
+    seq: Box<dyn Sequence>
+}
 
-        if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
-            println!("Error serving connection: {:?}", err);
+impl Subsample {
+    pub fn new(stride: usize, seq: Box<dyn Sequence>) -> Box<Subsample> {
+        Box::new(Subsample { stride, seq })
+    }
+}
+
+impl Sequence for Subsample {
+    fn k_th(&self, k: usize) -> f64 {
+        match k.checked_mul(self.stride) {
+            Some(k) => self.seq.k_th(k),
+            None => f64::NAN,
         }
-    });  
     }
-}
\ No newline at end of file
+}
+
+// Convolution of `seq1` with the first `len` elements of `seq2` as the
+// kernel. Indices of `seq1` that would be negative are clamped to 0.
+pub struct Convolve {
+    len: usize,
+    seq1: Box<dyn Sequence>,
+    seq2: Box<dyn Sequence>
+}
+
+impl Convolve {
+    pub fn new(len: usize, seq1: Box<dyn Sequence>, seq2: Box<dyn Sequence>) -> Box<Convolve> {
+        Box::new(Convolve { len, seq1, seq2 })
+    }
+}
+
+impl Sequence for Convolve {
+    fn k_th(&self, k: usize) -> f64 {
+        (0..self.len)
+            .take_while(|_| !cancelled())
+            .map(|i| self.seq1.k_th(k.saturating_sub(i)) * self.seq2.k_th(i))
+            .sum()
+    }
+}
+
+// Reads `seq1` at the indices produced by `seq2`. Indices are truncated
+// towards zero; a negative or NaN index has no element to read, so it gives 0.
+pub struct Compose {
+    seq1: Box<dyn Sequence>,
+    seq2: Box<dyn Sequence>
+}
+
+impl Compose {
+    pub fn new(seq1: Box<dyn Sequence>, seq2: Box<dyn Sequence>) -> Box<Compose> {
+        Box::new(Compose { seq1, seq2 })
+    }
+}
+
+impl Sequence for Compose {
+    fn k_th(&self, k: usize) -> f64 {
+        let index = self.seq2.k_th(k);
+        if index.is_nan() || index < 0.0 {
+            return 0.0;
+        }
+        self.seq1.k_th(index as usize)
+    }
+}
+
+// Reads a sequence at the Fibonacci numbers F(0) = 0, F(1) = 1, ... From
+// k = 94 on F(k) no longer fits an index, so those elements are NaN.
+pub struct FibonacciSample {
+    seq: Box<dyn Sequence>
+}
+
+impl FibonacciSample {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<FibonacciSample> {
+        Box::new(FibonacciSample { seq })
+    }
+}
+
+impl Sequence for FibonacciSample {
+    fn k_th(&self, k: usize) -> f64 {
+        // b runs one term ahead, so it needs the wider type to get F(93).
+        let (mut a, mut b): (u128, u128) = (0, 1);
+        for _ in 0..k {
+            let Some(next) = a.checked_add(b) else {
+                return f64::NAN;
+            };
+            (a, b) = (b, next);
+        }
+        match usize::try_from(a) {
+            Ok(index) => self.seq.k_th(index),
+            Err(_) => f64::NAN,
+        }
+    }
+}
+
+// Uniformly distributed values in `[lo, hi)`. Each element gets its own
+// generator seeded from the seed and the index, so the same request always
+// returns the same values.
+pub struct Random {
+    seed: u64,
+    lo: f64,
+    hi: f64
+}
+
+impl Random {
+    pub fn new(seed: u64, lo: f64, hi: f64) -> Box<Random> {
+        Box::new(Random { seed, lo, hi })
+    }
+}
+
+impl Sequence for Random {
+    fn k_th(&self, k: usize) -> f64 {
+        let mut rng = StdRng::seed_from_u64(self.seed ^ (k as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        self.lo + (self.hi - self.lo) * rng.gen::<f64>()
+    }
+}
+
+pub struct Gaussian {
+    seed: u64,
+    mean: f64,
+    stddev: f64
+}
+
+impl Gaussian {
+    pub fn new(seed: u64, mean: f64, stddev: f64) -> Box<Gaussian> {
+        Box::new(Gaussian { seed, mean, stddev })
+    }
+}
+
+impl Sequence for Gaussian {
+    fn k_th(&self, k: usize) -> f64 {
+        // Seeded the same way as Random, so each element depends only on (seed, k).
+        let mut rng = StdRng::seed_from_u64(self.seed ^ (k as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let z: f64 = rng.sample(StandardNormal);
+        self.mean + self.stddev * z
+    }
+}
+
+pub struct Sine {
+    amplitude: f64,
+    frequency: f64,
+    phase: f64
+}
+
+impl Sine {
+    pub fn new(amplitude: f64, frequency: f64, phase: f64) -> Box<Sine> {
+        Box::new(Sine { amplitude, frequency, phase })
+    }
+}
+
+impl Sequence for Sine {
+    fn k_th(&self, k: usize) -> f64 {
+        self.amplitude * (self.frequency * k as f64 + self.phase).sin()
+    }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        self.amplitude * (self.frequency * k as f64 + self.phase).sin()
+    }
+}
+
+pub struct Cosine {
+    amplitude: f64,
+    frequency: f64,
+    phase: f64
+}
+
+impl Cosine {
+    pub fn new(amplitude: f64, frequency: f64, phase: f64) -> Box<Cosine> {
+        Box::new(Cosine { amplitude, frequency, phase })
+    }
+}
+
+impl Sequence for Cosine {
+    fn k_th(&self, k: usize) -> f64 {
+        self.amplitude * (self.frequency * k as f64 + self.phase).cos()
+    }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        self.amplitude * (self.frequency * k as f64 + self.phase).cos()
+    }
+}
+
+pub struct Index;
+
+impl Index {
+    pub fn new() -> Box<Index> {
+        Box::new(Index)
+    }
+}
+
+impl Sequence for Index {
+    fn k_th(&self, k: usize) -> f64 {
+        k as f64
+    }
+
+    fn k_th_signed(&self, k: i64) -> f64 {
+        k as f64
+    }
+}
+
+pub struct Step {
+    n: f64
+}
+
+impl Step {
+    pub fn new(n: f64) -> Box<Step> {
+        Box::new(Step { n })
+    }
+}
+
+impl Sequence for Step {
+    fn k_th(&self, k: usize) -> f64 {
+        if k as f64 >= self.n { 1.0 } else { 0.0 }
+    }
+}
+
+pub struct Impulse {
+    n: f64
+}
+
+impl Impulse {
+    pub fn new(n: f64) -> Box<Impulse> {
+        Box::new(Impulse { n })
+    }
+}
+
+impl Sequence for Impulse {
+    fn k_th(&self, k: usize) -> f64 {
+        if k as f64 == self.n { 1.0 } else { 0.0 }
+    }
+}
+
+pub struct BitCount;
+
+impl BitCount {
+    pub fn new() -> Box<BitCount> {
+        Box::new(BitCount)
+    }
+}
+
+impl Sequence for BitCount {
+    fn k_th(&self, k: usize) -> f64 {
+        k.count_ones() as f64
+    }
+}
+
+pub struct DigitSum;
+
+impl DigitSum {
+    pub fn new() -> Box<DigitSum> {
+        Box::new(DigitSum)
+    }
+}
+
+impl Sequence for DigitSum {
+    fn k_th(&self, k: usize) -> f64 {
+        let mut k = k;
+        let mut sum = 0;
+        while k > 0 {
+            sum += k % 10;
+            k /= 10;
+        }
+        sum as f64
+    }
+}
+
+pub struct DivisorCount;
+
+impl DivisorCount {
+    pub fn new() -> Box<DivisorCount> {
+        Box::new(DivisorCount)
+    }
+}
+
+impl Sequence for DivisorCount {
+    fn k_th(&self, k: usize) -> f64 {
+        let mut count = 0;
+        let mut d = 1;
+        // d <= k / d rather than d * d <= k, which overflows for large k.
+        while d <= k / d && !cancelled() {
+            if k.is_multiple_of(d) {
+                count += if d == k / d { 1 } else { 2 };
+            }
+            d += 1;
+        }
+        count as f64
+    }
+}
+
+pub struct ThueMorse;
+
+impl ThueMorse {
+    pub fn new() -> Box<ThueMorse> {
+        Box::new(ThueMorse)
+    }
+}
+
+impl Sequence for ThueMorse {
+    fn k_th(&self, k: usize) -> f64 {
+        (k.count_ones() % 2) as f64
+    }
+}
+
+pub struct PowerOfTwo;
+
+impl PowerOfTwo {
+    pub fn new() -> Box<PowerOfTwo> {
+        Box::new(PowerOfTwo)
+    }
+}
+
+impl Sequence for PowerOfTwo {
+    fn k_th(&self, k: usize) -> f64 {
+        (k as f64).exp2()
+    }
+}
+
+pub struct PSeries {
+    p: f64
+}
+
+impl PSeries {
+    pub fn new(p: f64) -> Box<PSeries> {
+        Box::new(PSeries { p })
+    }
+}
+
+impl Sequence for PSeries {
+    fn k_th(&self, k: usize) -> f64 {
+        // Starts at 1/1^p, so the base is never 0.
+        1.0 / (k as f64 + 1.0).powf(self.p)
+    }
+}
+
+pub struct Ema {
+    alpha: f64,
+    seq: Box<dyn Sequence>
+}
+
+impl Ema {
+    pub fn new(alpha: f64, seq: Box<dyn Sequence>) -> Box<Ema> {
+        Box::new(Ema { alpha, seq })
+    }
+}
+
+impl Sequence for Ema {
+    fn range(&self, range: Range) -> Vec<f64> {
+        let mut result = Vec::new();
+        let values = self.seq.range(Range { from: 0, to: range.to, step: 1 });
+        // TakeUntil may end before the first element.
+        let Some(mut ema) = values.first().copied() else {
+            return result;
+        };
+        for (k, value) in values.into_iter().enumerate() {
+            if k > 0 {
+                ema = self.alpha * value + (1.0 - self.alpha) * ema;
+            }
+            if range.contains(k as u64) {
+                result.push(ema);
+            }
+        }
+        result
+    }
+}
+
+pub struct RunningMax {
+    seq: Box<dyn Sequence>
+}
+
+impl RunningMax {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<RunningMax> {
+        Box::new(RunningMax { seq })
+    }
+}
+
+impl Sequence for RunningMax {
+    fn range(&self, range: Range) -> Vec<f64> {
+        let mut result = Vec::new();
+        let mut max = f64::NEG_INFINITY;
+        for (k, value) in self.seq.range(Range { from: 0, to: range.to, step: 1 }).into_iter().enumerate() {
+            max = max.max(value);
+            if range.contains(k as u64) {
+                result.push(max);
+            }
+        }
+        result
+    }
+}
+
+pub struct RunningMin {
+    seq: Box<dyn Sequence>
+}
+
+impl RunningMin {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<RunningMin> {
+        Box::new(RunningMin { seq })
+    }
+}
+
+impl Sequence for RunningMin {
+    fn range(&self, range: Range) -> Vec<f64> {
+        let mut result = Vec::new();
+        let mut min = f64::INFINITY;
+        for (k, value) in self.seq.range(Range { from: 0, to: range.to, step: 1 }).into_iter().enumerate() {
+            min = min.min(value);
+            if range.contains(k as u64) {
+                result.push(min);
+            }
+        }
+        result
+    }
+}
+
+pub struct RunningProduct {
+    seq: Box<dyn Sequence>
+}
+
+impl RunningProduct {
+    pub fn new(seq: Box<dyn Sequence>) -> Box<RunningProduct> {
+        Box::new(RunningProduct { seq })
+    }
+}
+
+impl Sequence for RunningProduct {
+    fn range(&self, range: Range) -> Vec<f64> {
+        let mut result = Vec::new();
+        let mut product = 1.0;
+        for (k, value) in self.seq.range(Range { from: 0, to: range.to, step: 1 }).into_iter().enumerate() {
+            product *= value;
+            if range.contains(k as u64) {
+                result.push(product);
+            }
+        }
+        result
+    }
+}
+
+// Sample standard deviation of the last `window` elements. Before a full
+// window is available (k < window - 1) it is taken over the elements from
+// index 0 on, and a single element has a deviation of 0.
+pub struct RollingStd {
+    window: usize,
+    seq: Box<dyn Sequence>
+}
+
+impl RollingStd {
+    pub fn new(window: usize, seq: Box<dyn Sequence>) -> Box<RollingStd> {
+        Box::new(RollingStd { window, seq })
+    }
+}
+
+impl Sequence for RollingStd {
+    fn range(&self, range: Range) -> Vec<f64> {
+        let mut result = Vec::new();
+        let values = self.seq.range(Range { from: 0, to: range.to, step: 1 });
+        // Sums are taken relative to the first element, so a constant
+        // sequence comes out as exactly 0 instead of rounding noise.
+        // TakeUntil may end before the first element.
+        let Some(shift) = values.first().copied() else {
+            return result;
+        };
+        let mut sum = 0.0;
+        let mut sum_of_squares = 0.0;
+        for (k, value) in values.iter().enumerate() {
+            let x = value - shift;
+            sum += x;
+            sum_of_squares += x * x;
+            if k >= self.window {
+                let old = values[k - self.window] - shift;
+                sum -= old;
+                sum_of_squares -= old * old;
+            }
+            if range.contains(k as u64) {
+                let n = (k + 1).min(self.window) as f64;
+                if n < 2.0 {
+                    result.push(0.0);
+                } else {
+                    let variance = (sum_of_squares - sum * sum / n) / (n - 1.0);
+                    result.push(variance.max(0.0).sqrt());
+                }
+            }
+        }
+        result
+    }
+}
+
+// Ends at the first element that exceeds the threshold, so a range stops
+// there and returns fewer elements than requested. Like `Reverse`, this is
+// only defined over a range and calling `k_th` panics.
+pub struct TakeUntil {
+    threshold: f64,
+    seq: Box<dyn Sequence>
+}
+
+impl TakeUntil {
+    pub fn new(threshold: f64, seq: Box<dyn Sequence>) -> Box<TakeUntil> {
+        Box::new(TakeUntil { threshold, seq })
+    }
+}
+
+impl Sequence for TakeUntil {
+    fn k_th(&self, _k: usize) -> f64 {
+        panic!("TakeUntil is only defined over a range")
+    }
+    fn range(&self, range: Range) -> Vec<f64> {
+        let mut result = Vec::new();
+        for k in (0..=range.to).take_while(|_| !cancelled()) {
+            let value = self.seq.k_th(k as usize);
+            if value > self.threshold {
+                break;
+            }
+            if range.contains(k) {
+                result.push(value);
+            }
+        }
+        result
+    }
+}
+
+pub struct Threshold {
+    t: f64,
+    seq: Box<dyn Sequence>
+}
+
+impl Threshold {
+    pub fn new(t: f64, seq: Box<dyn Sequence>) -> Box<Threshold> {
+        Box::new(Threshold { t, seq })
+    }
+}
+
+impl Sequence for Threshold {
+    fn k_th(&self, k: usize) -> f64 {
+        if self.seq.k_th(k) >= self.t { 1.0 } else { 0.0 }
+    }
+}
+
+type Constructor = fn(&[f64], Vec<Box<dyn Sequence>>) -> Result<Box<dyn Sequence>, RequestError>;
+
+// Everything needed to list and build one of our sequences. Parameter and
+// sequence counts are checked before build is called; the parameter count is
+// the number of parameters, each given as name and description.
+struct Registration {
+    name: &'static str,
+    description: &'static str,
+    parameters: &'static [(&'static str, &'static str)],
+    sequences: u32,
+    // The parameter count is a minimum, not an exact count.
+    variable_parameters: bool,
+    // k_th panics, so only a parent that reads it through range may use it.
+    range_only: bool,
+    // The sub-sequences are only ever read through range.
+    reads_ranges: bool,
+    // Computes every element from index 0 up to the end of the range, so the
+    // end is what costs time and memory, not the number of elements.
+    from_zero: bool,
+    build: Constructor,
+}
+
+// Moves the already counted sub-sequences out of the Vec.
+fn take<const N: usize>(subs: Vec<Box<dyn Sequence>>) -> [Box<dyn Sequence>; N] {
+    subs.try_into().ok().expect("sub-sequence count is checked before building")
+}
+
+static REGISTRY: &[Registration] = &[
+    Registration {
+        name: "Arithmetic",
+        description: "Arithmetic sequence",
+        parameters: &[("start", "First element"), ("step", "Difference between consecutive elements")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Arithmetic::new(p[0], p[1])),
+    },
+    Registration {
+        name: "Geometric",
+        description: "Geometric sequence",
+        parameters: &[("start", "First element"), ("factor", "Ratio between consecutive elements")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Geometric::new(p[0], p[1])),
+    },
+    Registration {
+        name: "Constant",
+        description: "Constant sequence",
+        parameters: &[("value", "Value of every element")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Constant::new(p[0])),
+    },
+    Registration {
+        name: "Sum",
+        description: "Element-wise sum of two sequences",
+        parameters: &[],
+        sequences: 2,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq1, seq2] = take(s);
+            Ok(Sum::new(seq1, seq2))
+        },
+    },
+    Registration {
+        name: "Prod",
+        description: "Element-wise product of two sequences",
+        parameters: &[],
+        sequences: 2,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq1, seq2] = take(s);
+            Ok(Prod::new(seq1, seq2))
+        },
+    },
+    Registration {
+        name: "Drop",
+        description: "Drops the first n elements of a sequence",
+        parameters: &[("n", "Number of elements dropped from the start")],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, s| {
+            let [seq] = take(s);
+            Ok(Drop::new(seq, p[0] as usize))
+        },
+    },
+    Registration {
+        name: "LinComb",
+        description: "Linear combination of two sequences",
+        parameters: &[("a", "Factor of the first sequence"), ("b", "Factor of the second sequence"), ("c", "Constant added to every element")],
+        sequences: 2,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, s| {
+            let [seq1, seq2] = take(s);
+            Ok(LinComb::new(p[0], p[1], p[2], seq1, seq2))
+        },
+    },
+    Registration {
+        name: "Binomial",
+        description: "Binomial coefficients C(n, k), the n-th row of Pascal's triangle",
+        parameters: &[("n", "Row of Pascal's triangle")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Binomial::new(p[0] as usize)),
+    },
+    Registration {
+        name: "Padovan",
+        description: "Padovan sequence P(n) = P(n-2) + P(n-3) starting 1, 1, 1",
+        parameters: &[],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: true,
+        build: |_, _| Ok(Padovan::new()),
+    },
+    Registration {
+        name: "Catalan",
+        description: "Catalan numbers",
+        parameters: &[],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: true,
+        build: |_, _| Ok(Catalan::new()),
+    },
+    Registration {
+        name: "Logistic",
+        description: "Logistic map x(n+1) = r * x(n) * (1 - x(n)) starting at x0 between 0 and 1",
+        parameters: &[("r", "Growth rate"), ("x0", "First element, between 0 and 1")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: true,
+        build: |p, _| {
+            if !(0.0..=1.0).contains(&p[1]) {
+                return Err(RequestError::new(StatusCode::BAD_REQUEST, "Logistic x0 must be between 0 and 1"));
+            }
+            Ok(Logistic::new(p[0], p[1]))
+        },
+    },
+    Registration {
+        name: "Recursive",
+        description: "Recursive sequence of the form x(n) = ax(n-1) + bx(n-2)",
+        parameters: &[("x0", "Element at index 0"), ("x1", "Element at index 1"), ("a", "Factor of the previous element"), ("b", "Factor of the element before that")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: true,
+        build: |p, _| Ok(Recursive::new(p[0], p[1], p[2], p[3])),
+    },
+    Registration {
+        name: "Average",
+        description: "Element-wise average of two given sequences",
+        parameters: &[],
+        sequences: 2,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq1, seq2] = take(s);
+            Ok(Average::new(seq1, seq2))
+        },
+    },
+    Registration {
+        name: "Cyclic",
+        description: "Sequence that cycles over a specified number of starting terms of another sequence",
+        parameters: &[("cycle_length", "Number of starting elements repeated, at least 1")],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, s| {
+            if p[0] < 1.0 {
+                return Err(RequestError::new(StatusCode::BAD_REQUEST, "Cyclic cycle_length must be at least 1"));
+            }
+            let [seq] = take(s);
+            Ok(Cyclic::new(seq, p[0] as usize))
+        },
+    },
+    Registration {
+        name: "Alternating",
+        description: "Sequence where the sign of each element alternates based on its index.",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(Alternating::new(seq))
+        },
+    },
+    Registration {
+        name: "Smoothed",
+        description: "Sequence where each element is the average of itself and its immediate neighbors",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(Smoothed::new(seq))
+        },
+    },
+    Registration {
+        name: "Reverse",
+        description: "Requested range of a sequence in reverse order",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: true,
+        reads_ranges: true,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(Reverse::new(seq))
+        },
+    },
+    Registration {
+        name: "Normalize",
+        description: "Requested range of a sequence rescaled linearly to [0, 1]",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: true,
+        reads_ranges: true,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(Normalize::new(seq))
+        },
+    },
+    Registration {
+        name: "PeakNormalize",
+        description: "Requested range of a sequence divided by its largest absolute value, into [-1, 1]",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: true,
+        reads_ranges: true,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(PeakNormalize::new(seq))
+        },
+    },
+    Registration {
+        name: "Clamp",
+        description: "Sequence with every element clamped between lo and hi",
+        parameters: &[("lo", "Lower bound"), ("hi", "Upper bound")],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, s| {
+            let [seq] = take(s);
+            Ok(Clamp::new(p[0], p[1], seq))
+        },
+    },
+    Registration {
+        name: "ClampBetween",
+        description: "First sequence clamped between the second (lower bound) and the third (upper bound)",
+        parameters: &[],
+        sequences: 3,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq1, seq2, seq3] = take(s);
+            Ok(ClampBetween::new(seq1, seq2, seq3))
+        },
+    },
+    Registration {
+        name: "Abs",
+        description: "Absolute value of every element of a sequence",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(Abs::new(seq))
+        },
+    },
+    Registration {
+        name: "Modulo",
+        description: "Every element of a sequence wrapped into [0, m)",
+        parameters: &[("m", "Modulus, not 0")],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, s| {
+            if p[0] == 0.0 {
+                return Err(RequestError::new(StatusCode::BAD_REQUEST, "Modulo by zero"));
+            }
+            let [seq] = take(s);
+            Ok(Modulo::new(p[0], seq))
+        },
+    },
+    Registration {
+        name: "MovingAverage",
+        description: "Average of the elements from k - w/2 to k + w/2 of a sequence",
+        parameters: &[("window", "Number of elements averaged, centred on k")],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, s| {
+            let [seq] = take(s);
+            Ok(MovingAverage::new(p[0] as usize, seq))
+        },
+    },
+    Registration {
+        name: "Subsample",
+        description: "Every s-th element of a sequence",
+        parameters: &[("step", "Distance between the elements taken")],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, s| {
+            let [seq] = take(s);
+            Ok(Subsample::new(p[0] as usize, seq))
+        },
+    },
+    Registration {
+        name: "Ema",
+        description: "Exponential moving average of a sequence with smoothing factor alpha in [0, 1]",
+        parameters: &[("alpha", "Smoothing factor between 0 and 1, the weight of the newest element")],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: true,
+        from_zero: true,
+        build: |p, s| {
+            if !(0.0..=1.0).contains(&p[0]) {
+                return Err(RequestError::new(StatusCode::BAD_REQUEST, "Ema alpha must be between 0 and 1"));
+            }
+            let [seq] = take(s);
+            Ok(Ema::new(p[0], seq))
+        },
+    },
+    Registration {
+        name: "RunningMax",
+        description: "Largest element of a sequence up to and including index k",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: true,
+        from_zero: true,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(RunningMax::new(seq))
+        },
+    },
+    Registration {
+        name: "RunningMin",
+        description: "Smallest element of a sequence up to and including index k",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: true,
+        from_zero: true,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(RunningMin::new(seq))
+        },
+    },
+    Registration {
+        name: "RunningProduct",
+        description: "Product of the elements of a sequence up to and including index k",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: true,
+        from_zero: true,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(RunningProduct::new(seq))
+        },
+    },
+    Registration {
+        name: "RollingStd",
+        description: "Sample standard deviation of the last w elements of the sequence",
+        parameters: &[("window", "Number of elements the deviation is taken over, at least 1")],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: true,
+        from_zero: true,
+        build: |p, s| {
+            if p[0] < 1.0 {
+                return Err(RequestError::new(StatusCode::BAD_REQUEST, "RollingStd window must be at least 1"));
+            }
+            let [seq] = take(s);
+            Ok(RollingStd::new(p[0] as usize, seq))
+        },
+    },
+    Registration {
+        name: "TakeUntil",
+        description: "Sequence that ends before its first element larger than the threshold",
+        parameters: &[("threshold", "Largest element the sequence may still contain")],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: true,
+        reads_ranges: false,
+        from_zero: true,
+        build: |p, s| {
+            let [seq] = take(s);
+            Ok(TakeUntil::new(p[0], seq))
+        },
+    },
+    Registration {
+        name: "Threshold",
+        description: "1 where the sequence is at least the threshold, 0 elsewhere",
+        parameters: &[("threshold", "Smallest element that gives 1")],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, s| {
+            let [seq] = take(s);
+            Ok(Threshold::new(p[0], seq))
+        },
+    },
+    Registration {
+        name: "Negate",
+        description: "Sequence with the sign of every element flipped",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(Negate::new(seq))
+        },
+    },
+    Registration {
+        name: "GrowthRatio",
+        description: "Ratio of each next element to the current one",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(GrowthRatio::new(seq))
+        },
+    },
+    Registration {
+        name: "Floor",
+        description: "Elements of the sequence rounded down",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(Floor::new(seq))
+        },
+    },
+    Registration {
+        name: "Ceil",
+        description: "Elements of the sequence rounded up",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(Ceil::new(seq))
+        },
+    },
+    Registration {
+        name: "Round",
+        description: "Elements of the sequence rounded to the nearest integer, halves away from zero",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(Round::new(seq))
+        },
+    },
+    Registration {
+        name: "Interleave",
+        description: "Even elements from the first sequence, odd elements from the second",
+        parameters: &[],
+        sequences: 2,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq1, seq2] = take(s);
+            Ok(Interleave::new(seq1, seq2))
+        },
+    },
+    Registration {
+        name: "Concat",
+        description: "First n elements of the first sequence followed by the second sequence",
+        parameters: &[("n", "Number of elements taken from the first sequence")],
+        sequences: 2,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, s| {
+            let [seq1, seq2] = take(s);
+            Ok(Concat::new(p[0] as usize, seq1, seq2))
+        },
+    },
+    Registration {
+        name: "Piecewise",
+        description: "First sequence below index n and the second from index n on, without reindexing",
+        parameters: &[("n", "Index from which the second sequence is used")],
+        sequences: 2,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, s| {
+            let [seq1, seq2] = take(s);
+            Ok(Piecewise::new(p[0] as usize, seq1, seq2))
+        },
+    },
+    Registration {
+        name: "Convolve",
+        description: "Convolution of the first sequence with the first len elements of the second",
+        parameters: &[("len", "Number of elements of the second sequence used as the kernel")],
+        sequences: 2,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, s| {
+            let [seq1, seq2] = take(s);
+            Ok(Convolve::new(p[0] as usize, seq1, seq2))
+        },
+    },
+    Registration {
+        name: "Compose",
+        description: "First sequence read at the indices given by the second",
+        parameters: &[],
+        sequences: 2,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq1, seq2] = take(s);
+            Ok(Compose::new(seq1, seq2))
+        },
+    },
+    Registration {
+        name: "FibonacciSample",
+        description: "Sequence read at the Fibonacci numbers 0, 1, 1, 2, 3, 5, ...",
+        parameters: &[],
+        sequences: 1,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, s| {
+            let [seq] = take(s);
+            Ok(FibonacciSample::new(seq))
+        },
+    },
+    Registration {
+        name: "Repeat",
+        description: "Repeats the block of values given as parameters (at least one)",
+        parameters: &[("block", "Values repeated in order; any number of them may follow")],
+        sequences: 0,
+        variable_parameters: true,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Repeat::new(p.to_vec())),
+    },
+    Registration {
+        name: "Literal",
+        description: "The values given as parameters (at least one), then zeros",
+        parameters: &[("values", "Values in order; any number of them may follow")],
+        sequences: 0,
+        variable_parameters: true,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Literal::new(p.to_vec())),
+    },
+    Registration {
+        name: "Random",
+        description: "Reproducible uniformly distributed values between lo and hi for a given seed",
+        parameters: &[("seed", "Seed, the same one always gives the same values"), ("lo", "Lower bound, inclusive"), ("hi", "Upper bound, exclusive")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Random::new(p[0] as u64, p[1], p[2])),
+    },
+    Registration {
+        name: "Gaussian",
+        description: "Normally distributed noise with the given mean and standard deviation, reproducible for the same seed",
+        parameters: &[("seed", "Seed, the same one always gives the same values"), ("mean", "Mean"), ("stddev", "Standard deviation")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Gaussian::new(p[0] as u64, p[1], p[2])),
+    },
+    Registration {
+        name: "Sine",
+        description: "Sampled sine wave amplitude * sin(frequency * k + phase)",
+        parameters: &[("amplitude", "Amplitude"), ("frequency", "Angular frequency in radians per index"), ("phase", "Phase in radians")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Sine::new(p[0], p[1], p[2])),
+    },
+    Registration {
+        name: "Cosine",
+        description: "Sampled cosine wave amplitude * cos(frequency * k + phase)",
+        parameters: &[("amplitude", "Amplitude"), ("frequency", "Angular frequency in radians per index"), ("phase", "Phase in radians")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Cosine::new(p[0], p[1], p[2])),
+    },
+    Registration {
+        name: "Index",
+        description: "The index k itself",
+        parameters: &[],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, _| Ok(Index::new()),
+    },
+    Registration {
+        name: "Step",
+        description: "Heaviside step, 0 for k < n and 1 from k = n on",
+        parameters: &[("n", "First index that gives 1")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Step::new(p[0])),
+    },
+    Registration {
+        name: "Impulse",
+        description: "Unit impulse, 1 at k = n and 0 elsewhere",
+        parameters: &[("n", "Only index that gives 1")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(Impulse::new(p[0])),
+    },
+    Registration {
+        name: "BitCount",
+        description: "Number of set bits in the binary representation of k",
+        parameters: &[],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, _| Ok(BitCount::new()),
+    },
+    Registration {
+        name: "DigitSum",
+        description: "Sum of the decimal digits of k",
+        parameters: &[],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, _| Ok(DigitSum::new()),
+    },
+    Registration {
+        name: "DivisorCount",
+        description: "Number of positive divisors of k, 0 for k = 0",
+        parameters: &[],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, _| Ok(DivisorCount::new()),
+    },
+    Registration {
+        name: "ThueMorse",
+        description: "Thue-Morse sequence, the parity of the number of set bits in k",
+        parameters: &[],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, _| Ok(ThueMorse::new()),
+    },
+    Registration {
+        name: "PowerOfTwo",
+        description: "Powers of two 2^k",
+        parameters: &[],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |_, _| Ok(PowerOfTwo::new()),
+    },
+    Registration {
+        name: "PSeries",
+        description: "Terms 1 / (k + 1)^p of the p-series",
+        parameters: &[("p", "Exponent")],
+        sequences: 0,
+        variable_parameters: false,
+        range_only: false,
+        reads_ranges: false,
+        from_zero: false,
+        build: |p, _| Ok(PSeries::new(p[0])),
+    },
+];
+
+fn sequences() -> Vec<SequenceInfo> {
+    REGISTRY
+        .iter()
+        .map(|entry| SequenceInfo {
+            name: entry.name.to_string(),
+            description: entry.description.to_string(),
+            parameters: entry.parameters.len() as u32,
+            parameter_names: entry.parameters.iter().map(|(name, _)| name.to_string()).collect(),
+            parameter_descriptions: entry.parameters.iter().map(|(_, description)| description.to_string()).collect(),
+            sequences: entry.sequences,
+        })
+        .collect()
+}
+
+// Looks up the sequence a /sequence/{name} path names. An exact match wins,
+// otherwise case is ignored, so /sequence/arithmetic is Arithmetic rather
+// than something to delegate.
+fn find_sequence<'a>(seqs: &'a [SequenceInfo], path: &str) -> Option<&'a SequenceInfo> {
+    find_by_name(seqs, path.strip_prefix("/sequence/")?)
+}
+
+fn find_by_name<'a>(seqs: &'a [SequenceInfo], name: &str) -> Option<&'a SequenceInfo> {
+    seqs.iter()
+        .find(|x| x.name == name)
+        .or_else(|| seqs.iter().find(|x| x.name.eq_ignore_ascii_case(name)))
+}
+
+// 404 for a path under /sequence/ that names none of our sequences.
+fn unknown_sequence_response(path: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    match path.strip_prefix("/sequence/") {
+        Some(name) if !name.is_empty() => error_response(RequestError::new(
+            StatusCode::NOT_FOUND,
+            format!("No sequence named {}, GET /sequence lists them", name),
+        )),
+        _ => not_found(),
+    }
+}
+
+// Stand-in for a sequence we don't have, so it can be delegated by name.
+fn unknown_sequence(name: &str) -> SequenceInfo {
+    SequenceInfo {
+        name: name.to_string(),
+        description: "".to_string(),
+        parameters: 0,
+        parameter_names: Vec::new(),
+        parameter_descriptions: Vec::new(),
+        sequences: 0,
+    }
+}
+
+fn get_project() -> Project {
+    let name = "Lucija & Lev".to_string();
+    let ip = "127.0.0.1".to_string();
+    Project {
+        id: Some(format!("{}@{}:{}", name, ip, PORT)),
+        name,
+        ip,
+        port: PORT,
+    }
+}
+
+fn get_health(state: &State) -> Health {
+    let status = state.status.lock().unwrap();
+    Health {
+        uptime_secs: status.started.elapsed().as_secs(),
+        registered: status.registered,
+        port: PORT,
+        peers: status.peers,
+    }
+}
+
+// Ready once we have registered at least once; with delegation disabled we
+// never register, so we are ready straight away.
+fn is_ready(state: &State) -> bool {
+    state.config.no_delegate || state.status.lock().unwrap().ever_registered
+}
+
+async fn ping_registry(state: &State) -> RegistryPing {
+    let started = Instant::now();
+    match send_get(state.config.projects_url()).await {
+        Ok(_) => RegistryPing {
+            reachable: true,
+            latency_ms: Some(started.elapsed().as_millis() as u64),
+        },
+        Err(e) => {
+            warn!("Registry unreachable: {}", e);
+            RegistryPing { reachable: false, latency_ms: None }
+        }
+    }
+}
+
+fn get_capabilities(config: &Config) -> Capabilities {
+    Capabilities {
+        content_types: vec!["application/json".to_string()],
+        encodings: vec!["gzip".to_string()],
+        streaming: false,
+        http2: config.http2,
+        delegation: !config.no_delegate,
+        max_range_len: config.max_range_len,
+        body_limit: config.body_limit,
+        max_depth: config.max_depth,
+        max_evaluations: config.max_evaluations,
+        evaluation_timeout_secs: config.evaluation_timeout.as_secs(),
+        max_batch_items: config.max_batch_items,
+    }
+}
+
+// Commit and target are filled in by build.rs; the commit is missing when built outside git.
+fn get_version() -> Version {
+    Version {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        commit: option_env!("GIT_COMMIT").map(|commit| commit.to_string()),
+        target: env!("BUILD_TARGET").to_string(),
+    }
+}
+
+// Port of a registry entry, whether it is listed as a number or a string.
+fn project_port(project: &serde_json::Value) -> Option<u16> {
+    match &project["port"] {
+        serde_json::Value::Number(n) => n.as_u64().and_then(|p| u16::try_from(p).ok()),
+        serde_json::Value::String(s) => s.parse::<u16>().ok(),
+        _ => None,
+    }
+}
+
+// Whether a registry entry is this node.
+fn is_self(project: &serde_json::Value) -> bool {
+    project["ip"] == "127.0.0.1" && project_port(project) == Some(PORT)
+}
+
+fn count_peers(projects: &serde_json::Value) -> usize {
+    match projects.as_array() {
+        Some(projects) => projects
+            .iter()
+            .filter(|p| !is_self(p))
+            .count(),
+        None => 0,
+    }
+}
+
+// Round trip to each "ip:port", pinging all the ones not measured recently at
+// once. Unreachable peers, and ones that don't answer within
+// PING_TIMEOUT_MILLIS, get Duration::MAX, so they are tried last.
+async fn peer_latencies(addresses: &[String], state: &State) -> Vec<Duration> {
+    let mut latencies: Vec<Option<Duration>> = {
+        let status = state.status.lock().unwrap();
+        addresses
+            .iter()
+            .map(|address| match status.peer_latencies.get(address) {
+                Some((measured, latency)) if measured.elapsed() < Duration::from_secs(PEERS_CACHE_SECS) => Some(*latency),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let mut pings = tokio::task::JoinSet::new();
+    for (i, address) in addresses.iter().enumerate().filter(|&(i, _)| latencies[i].is_none()) {
+        let address = address.clone();
+        pings.spawn(async move {
+            let started = Instant::now();
+            let ping = send_get(format!("http://{}/ping", address));
+            let latency = match tokio::time::timeout(Duration::from_millis(PING_TIMEOUT_MILLIS), ping).await {
+                Ok(Ok(_)) => started.elapsed(),
+                Ok(Err(e)) => {
+                    warn!("Failed to ping {}: {}", address, e);
+                    Duration::MAX
+                }
+                Err(_) => {
+                    warn!("{} did not answer a ping within {} ms", address, PING_TIMEOUT_MILLIS);
+                    Duration::MAX
+                }
+            };
+            debug!(address, ?latency, "measured peer latency");
+            (i, latency)
+        });
+    }
+    while let Some(ping) = pings.join_next().await {
+        if let Ok((i, latency)) = ping {
+            latencies[i] = Some(latency);
+            state.status.lock().unwrap().peer_latencies.insert(addresses[i].clone(), (Instant::now(), latency));
+        }
+    }
+    latencies.into_iter().map(|latency| latency.unwrap_or(Duration::MAX)).collect()
+}
+
+// Maps "ip:port" of every reachable peer to the names of its sequences.
+async fn get_peers(state: &State) -> Result<BTreeMap<String, Vec<String>>, reqwest::Error> {
+    if state.config.no_delegate {
+        return Ok(BTreeMap::new());
+    }
+    if let Some((fetched, peers)) = &state.status.lock().unwrap().peer_sequences {
+        if fetched.elapsed() < Duration::from_secs(PEERS_CACHE_SECS) {
+            return Ok(peers.clone());
+        }
+    }
+
+    let b = send_get(state.config.projects_url()).await?;
+    let projects = match serde_json::from_str(&b) {
+        Ok(serde_json::Value::Array(projects)) => projects,
+        _ => {
+            warn!("Registry sent something other than a list of projects: {}", b);
+            Vec::new()
+        }
+    };
+    // One bad entry shouldn't hide all the others.
+    let addresses: Vec<String> = projects
+        .iter()
+        .filter(|p| !is_self(p))
+        .filter_map(|p| match (p["ip"].as_str(), project_port(p)) {
+            (Some(ip), Some(port)) => Some(format!("{}:{}", ip, port)),
+            _ => {
+                warn!("Skipping registry entry without an ip and port: {}", p);
+                None
+            }
+        })
+        .collect();
+
+    // All at once, so slow peers cost one timeout rather than one each.
+    let mut lists = tokio::task::JoinSet::new();
+    for address in addresses.iter().cloned() {
+        lists.spawn(async move {
+            let list = send_get(format!("http://{}/sequence", address)).await;
+            (address, list)
+        });
+    }
+    let mut peers = BTreeMap::new();
+    while let Some(list) = lists.join_next().await {
+        let Ok((address, list)) = list else {
+            continue;
+        };
+        match list {
+            Ok(data) => {
+                let seqs: Vec<serde_json::Value> = serde_json::from_str(&data).unwrap_or_default();
+                let names = seqs
+                    .iter()
+                    .filter_map(|x| x["name"].as_str().map(|name| name.to_string()))
+                    .collect();
+                peers.insert(address, names);
+            }
+            Err(e) => warn!("Failed to list sequences of {}: {}", address, e),
+        }
+    }
+
+    let mut status = state.status.lock().unwrap();
+    status.peers = addresses.len();
+    status.peer_sequences = Some((Instant::now(), peers.clone()));
+    Ok(peers)
+}
+
+fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
+    Full::new(chunk.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+// Limited counts the bytes as the frames arrive, so a missing or wrong
+// Content-Length doesn't get around the limit.
+async fn collect_body(req: Request<Incoming>, limit: usize) -> Result<String, RequestError> {
+    let whole_body = match Limited::new(req.into_body(), limit).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) if e.is::<LengthLimitError>() => {
+            return Err(RequestError::new(StatusCode::PAYLOAD_TOO_LARGE, format!("Body larger than {} bytes", limit)));
+        }
+        Err(e) => return Err(RequestError::new(StatusCode::BAD_REQUEST, format!("Failed to read body: {}", e))),
+    };
+    match String::from_utf8(whole_body.to_vec()) {
+        Ok(whole_body) => Ok(whole_body),
+        Err(_) => Err(RequestError::new(StatusCode::BAD_REQUEST, "Body is not valid UTF-8")),
+    }
+}
+
+fn empty() -> BoxBody<Bytes, hyper::Error> {
+    Empty::<Bytes>::new()
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+fn not_found() -> Response<BoxBody<Bytes, hyper::Error>> {
+    error_response(RequestError::new(StatusCode::NOT_FOUND, "Not found"))
+}
+
+// Answer to a HEAD request: the headers of the GET response, including the
+// Content-Length its body would have had.
+async fn without_body(response: Response<BoxBody<Bytes, hyper::Error>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let (mut parts, body) = response.into_parts();
+    let length = body.collect().await?.to_bytes().len();
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+    Ok(Response::from_parts(parts, empty()))
+}
+
+// Lets browser clients on the configured origin read our responses.
+fn add_cors_headers(response: &mut Response<BoxBody<Bytes, hyper::Error>>, origin: &str) {
+    let headers = response.headers_mut();
+    if let Ok(origin) = HeaderValue::from_str(origin) {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    }
+    headers.insert(ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("GET, HEAD, POST, OPTIONS"));
+    headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_static("Content-Type, If-None-Match"));
+    headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, HeaderValue::from_static("ETag, X-Request-Id"));
+}
+
+fn error_response(error: RequestError) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let body = ErrorResponse {
+        code: error.status.as_u16(),
+        message: error.message,
+    };
+    Response::builder()
+        .status(error.status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_string(&body).unwrap()))
+        .unwrap()
+}
+
+// Both fail on a status other than 2xx, so an error body is never taken for
+// a result.
+async fn send_post(url: String, body: String) -> Result<String, reqwest::Error> {
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS)).build()?;
+    let res = client.post(url).body(body).send().await?.error_for_status()?.text().await?;
+    Ok(res)
+}
+
+async fn send_get(url: String) -> Result<String, reqwest::Error> {
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS)).build()?;
+    let res = client.get(url).send().await?.error_for_status()?.text().await?;
+    Ok(res)
+}
+
+#[tracing::instrument(skip(req, sequence_info, state), fields(sequence = %sequence_info.name, request_id))]
+async fn handle_sequence_request(req: Request<Incoming>, sequence_info: &SequenceInfo, state: &State) -> Result<Response<BoxBody<Bytes, Error>>, hyper::Error> {
+    let offset: Option<usize> = query_param(req.uri().query(), "offset");
+    let limit: Option<usize> = query_param(req.uri().query(), "limit");
+    let precision: Option<u32> = query_param(req.uri().query(), "precision");
+    let if_none_match = req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let gzip = req
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(accepts_gzip);
+    let body = match collect_body(req, state.config.body_limit).await {
+        Ok(body) => body,
+        Err(e) => return Ok(error_response(e)),
+    };
+    debug!(%body, "received request");
+    let mut request: SequenceRequest = match serde_json::from_str(&body) {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(RequestError::new(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)))),
+    };
+    let request_id = request
+        .request_id
+        .get_or_insert_with(|| format!("{:016x}", thread_rng().gen::<u64>()))
+        .clone();
+    tracing::Span::current().record("request_id", request_id.as_str());
+
+    let format = Format { precision, offset, limit };
+    let result = match evaluate(request, sequence_info, state, format).await {
+        Ok(result) => result,
+        Err(e) => return Ok(with_request_id(error_response(e), &request_id)),
+    };
+
+    // Identical requests give identical results, so the request itself
+    // identifies the response, along with its encoding. The client's copy is
+    // only confirmed once the request has evaluated, so a tag of a failing
+    // request never matches.
+    let etag = etag(&sequence_info.name, &body, offset, limit, precision, gzip);
+    let response = Response::builder().header(ETAG, &etag).header(VARY, "Accept-Encoding");
+    if if_none_match.is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag)) {
+        let response = response.status(StatusCode::NOT_MODIFIED).body(empty()).unwrap();
+        return Ok(with_request_id(response, &request_id));
+    }
+
+    METRICS.observe_response_size(result.len());
+    let response = if gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(result.as_bytes()).unwrap();
+        response
+            .header(CONTENT_ENCODING, "gzip")
+            .body(full(encoder.finish().unwrap()))
+            .unwrap()
+    } else {
+        response.body(full(result)).unwrap()
+    };
+    Ok(with_request_id(response, &request_id))
+}
+
+// Ids that aren't valid header values are still forwarded, just not echoed.
+fn with_request_id(mut response: Response<BoxBody<Bytes, hyper::Error>>, request_id: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    response
+}
+
+fn etag(name: &str, body: &str, offset: Option<usize>, limit: Option<usize>, precision: Option<u32>, gzip: bool) -> String {
+    let mut hasher = DefaultHasher::new();
+    (name, body, offset, limit, precision, gzip).hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+// Whether an Accept-Encoding header allows gzip. A coding with q=0 is refused,
+// and gzip named on its own counts over *.
+fn accepts_gzip(header: &str) -> bool {
+    let mut gzip = None;
+    let mut any = None;
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim();
+        let q = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f64>().ok())
+            .unwrap_or(1.0);
+        if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+            gzip = Some(q > 0.0);
+        } else if coding == "*" {
+            any = Some(q > 0.0);
+        }
+    }
+    gzip.or(any).unwrap_or(false)
+}
+
+fn query_param<T: FromStr>(query: Option<&str>, key: &str) -> Option<T> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+impl Format {
+    // JSON of the values, rounded and sliced as asked. Non-finite values are
+    // sent as null.
+    fn render(&self, mut values: Vec<f64>) -> String {
+        if let Some(precision) = self.precision {
+            for x in values.iter_mut() {
+                *x = round(*x, precision);
+            }
+        }
+        if self.offset.is_none() && self.limit.is_none() {
+            return serde_json::to_string(&values).unwrap();
+        }
+        serde_json::to_string(&paginate(&values, self.offset, self.limit)).unwrap()
+    }
+
+    fn is_default(&self) -> bool {
+        self.precision.is_none() && self.offset.is_none() && self.limit.is_none()
+    }
+}
+
+// Rounds to the given number of decimals. Values too large to scale stay as
+// they are, since they have no decimals left to round.
+fn round(x: f64, precision: u32) -> f64 {
+    // f64 has no more than 16 significant decimals, and 10^precision must stay finite.
+    let scale = 10f64.powi(precision.min(16) as i32);
+    let scaled = x * scale;
+    if scaled.is_finite() {
+        scaled.round() / scale
+    } else {
+        x
+    }
+}
+
+// Slices an evaluated range after from/to/step have already been applied.
+fn paginate(values: &[f64], offset: Option<usize>, limit: Option<usize>) -> Page {
+    let total = values.len();
+    let offset = offset.unwrap_or(0).min(total);
+    let limit = limit.unwrap_or(total - offset).min(total - offset);
+    Page {
+        total,
+        offset,
+        limit,
+        values: values[offset..offset + limit].iter().map(|x| Some(*x).filter(|x| x.is_finite())).collect(),
+    }
+}
+
+// Recursive descent parser for the text form of a sequence, e.g.
+// `Sum(Arithmetic(0, 1), Geometric(1, 2))`. Numbers among the arguments are
+// the parameters and everything else the sub-sequences, each in order; a
+// sequence without arguments may leave out the parentheses.
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    max_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(input: &'a str, max_depth: usize) -> Result<SequenceSyntax, String> {
+        let mut parser = Parser { input, pos: 0, max_depth };
+        let syntax = parser.sequence(1)?;
+        parser.skip_whitespace();
+        if parser.pos < input.len() {
+            return Err(parser.error("end of input"));
+        }
+        Ok(syntax)
+    }
+
+    fn sequence(&mut self, depth: usize) -> Result<SequenceSyntax, String> {
+        if depth > self.max_depth {
+            return Err(format!("Sequences nested deeper than {} at position {}", self.max_depth, self.pos));
+        }
+        self.skip_whitespace();
+        let start = self.pos;
+        let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            self.pos = start;
+            return Err(self.error("a sequence name"));
+        }
+        let mut syntax = SequenceSyntax { name: name.to_string(), parameters: Vec::new(), sequences: Vec::new() };
+        self.skip_whitespace();
+        if !self.eat('(') {
+            return Ok(syntax);
+        }
+        self.skip_whitespace();
+        if self.eat(')') {
+            return Ok(syntax);
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek().is_some_and(|c| c.is_ascii_digit() || c == '-' || c == '+' || c == '.') {
+                let start = self.pos;
+                let number = self.take_while(|c| c.is_ascii_alphanumeric() || c == '-' || c == '+' || c == '.');
+                match number.parse::<f64>() {
+                    Ok(x) if x.is_finite() => syntax.parameters.push(x),
+                    _ => return Err(format!("Invalid number {} at position {}", number, start)),
+                }
+            } else {
+                syntax.sequences.push(Box::new(self.sequence(depth + 1)?));
+            }
+            self.skip_whitespace();
+            if self.eat(',') {
+                continue;
+            }
+            if self.eat(')') {
+                return Ok(syntax);
+            }
+            return Err(self.error("',' or ')'"));
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.pos;
+        while let Some(c) = self.peek().filter(|&c| f(c)) {
+            self.pos += c.len_utf8();
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn error(&self, expected: &str) -> String {
+        match self.peek() {
+            Some(c) => format!("Expected {} at position {}, found '{}'", expected, self.pos, c),
+            None => format!("Expected {} at position {}, found end of input", expected, self.pos),
+        }
+    }
+}
+
+async fn handle_parse_request(req: Request<Incoming>, state: &State) -> Result<Response<BoxBody<Bytes, Error>>, hyper::Error> {
+    let body = match collect_body(req, state.config.body_limit).await {
+        Ok(body) => body,
+        Err(e) => return Ok(error_response(e)),
+    };
+    match Parser::parse(&body, state.config.max_depth) {
+        Ok(syntax) => Ok(Response::new(full(serde_json::to_string(&syntax).unwrap()))),
+        Err(message) => Ok(error_response(RequestError::new(StatusCode::BAD_REQUEST, message))),
+    }
+}
+
+#[tracing::instrument(skip(req, state))]
+async fn handle_evaluate_request(req: Request<Incoming>, state: &State) -> Result<Response<BoxBody<Bytes, Error>>, hyper::Error> {
+    let body = match collect_body(req, state.config.body_limit).await {
+        Ok(body) => body,
+        Err(e) => return Ok(error_response(e)),
+    };
+    debug!(%body, "received evaluate request");
+    let EvaluateRequest { range, syntax } = match serde_json::from_str(&body) {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(RequestError::new(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)))),
+    };
+
+    let request = SequenceRequest {
+        range,
+        parameters: syntax.parameters,
+        sequences: syntax.sequences,
+        request_id: None,
+        peers: None,
+        signed_range: None,
+        indices: None,
+    };
+    let result = match evaluate_named(&syntax.name, request, state).await {
+        Ok(result) => result,
+        Err(e) => return Ok(error_response(e)),
+    };
+    METRICS.observe_response_size(result.len());
+    Ok(Response::new(full(result)))
+}
+
+// For the endpoints that name the sequence in the body rather than the path.
+async fn evaluate_named(name: &str, request: SequenceRequest, state: &State) -> Result<String, RequestError> {
+    let seqs = sequences();
+    match find_by_name(&seqs, name) {
+        Some(sequence_info) => evaluate(request, sequence_info, state, Format::default()).await,
+        None => evaluate(request, &unknown_sequence(name), state, Format::default()).await,
+    }
+}
+
+#[tracing::instrument(skip(req, state))]
+async fn handle_compare_request(req: Request<Incoming>, state: &State) -> Result<Response<BoxBody<Bytes, Error>>, hyper::Error> {
+    let body = match collect_body(req, state.config.body_limit).await {
+        Ok(body) => body,
+        Err(e) => return Ok(error_response(e)),
+    };
+    debug!(%body, "received compare request");
+    let compare: CompareRequest = match serde_json::from_str(&body) {
+        Ok(compare) => compare,
+        Err(e) => return Ok(error_response(RequestError::new(StatusCode::BAD_REQUEST, format!("Invalid compare request: {}", e)))),
+    };
+
+    let mut values = Vec::new();
+    for mut item in [compare.first, compare.second] {
+        // Divergences are reported as indices of the shared range, so
+        // neither request may be evaluated anywhere else.
+        if item.request.signed_range.is_some() || item.request.indices.is_some() {
+            return Ok(error_response(RequestError::new(
+                StatusCode::BAD_REQUEST,
+                "Compared requests are evaluated over the range of the comparison and can't have a signed_range or indices",
+            )));
+        }
+        item.request.range = compare.range;
+        let result = match evaluate_named(&item.name, item.request, state).await {
+            Ok(result) => result,
+            Err(e) => return Ok(error_response(e)),
+        };
+        // Only a peer's answer can fail to parse.
+        match serde_json::from_str::<Vec<Option<f64>>>(&result) {
+            Ok(result) => values.push(result),
+            Err(e) => {
+                warn!("Invalid result for {}: {}", item.name, e);
+                return Ok(error_response(RequestError::new(StatusCode::BAD_GATEWAY, format!("Got an invalid result for {}", item.name))));
+            }
+        }
+    }
+
+    let (first, second) = (&values[0], &values[1]);
+    // Non-finite values come back as null and only equal each other.
+    let position = (0..first.len().max(second.len())).find(|&i| match (first.get(i), second.get(i)) {
+        (Some(Some(a)), Some(Some(b))) => (a - b).abs() > compare.tolerance,
+        (Some(None), Some(None)) => false,
+        _ => true,
+    });
+    let comparison = Comparison {
+        equal: position.is_none(),
+        first_divergence: position.map(|i| compare.range.from + i as u64 * compare.range.step),
+    };
+    Ok(Response::new(full(serde_json::to_string(&comparison).unwrap())))
+}
+
+#[tracing::instrument(skip(req, state))]
+async fn handle_batch_request(req: Request<Incoming>, state: &State) -> Result<Response<BoxBody<Bytes, Error>>, hyper::Error> {
+    let body = match collect_body(req, state.config.body_limit).await {
+        Ok(body) => body,
+        Err(e) => return Ok(error_response(e)),
+    };
+    debug!(%body, "received batch request");
+    let items: Vec<BatchItem> = match serde_json::from_str(&body) {
+        Ok(items) => items,
+        Err(e) => return Ok(error_response(RequestError::new(StatusCode::BAD_REQUEST, format!("Invalid batch request: {}", e)))),
+    };
+    // Each item may be as long as a request of its own, so their number is
+    // what keeps a batch in bounds.
+    if items.len() > state.config.max_batch_items {
+        return Ok(error_response(RequestError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Batch has {} items, at most {} allowed", items.len(), state.config.max_batch_items),
+        )));
+    }
+
+    let mut results = Vec::new();
+    for item in items {
+        results.push(match evaluate_named(&item.name, item.request, state).await {
+            Ok(result) => serde_json::from_str(&result).unwrap_or(serde_json::Value::Null),
+            Err(e) => serde_json::json!({ "error": e.message }),
+        });
+    }
+    let result = serde_json::to_string(&results).unwrap();
+    METRICS.observe_response_size(result.len());
+    Ok(Response::new(full(result)))
+}
+
+// Checks the whole request and builds the requested sequence, or returns None
+// if it needs sequences only a peer has. Evaluating and validating both go
+// through here, so a request that validates is one we would evaluate.
+fn build(request: &SequenceRequest, sequence_info: &SequenceInfo, config: &Config) -> Result<Option<Box<dyn Sequence>>, RequestError> {
+    let step = request.signed_range.map_or(request.range.step, |range| range.step);
+    if step == 0 && request.indices.is_none() {
+        return Err(RequestError::new(StatusCode::BAD_REQUEST, "Range step must be positive"));
+    }
+    if request.signed_range.is_some_and(|range| range.step > i64::MAX as u64) && request.indices.is_none() {
+        return Err(RequestError::new(StatusCode::BAD_REQUEST, format!("Signed range step must be at most {}", i64::MAX)));
+    }
+    // Range-only sequences would only get the part of a signed range from 0
+    // on, and each index of a list as a range of its own.
+    let range_only = REGISTRY.iter().any(|entry| entry.name == sequence_info.name && entry.range_only);
+    if range_only && request.indices.is_some() {
+        return Err(RequestError::new(
+            StatusCode::BAD_REQUEST,
+            format!("{} is only defined over a range and can't be evaluated at a list of indices", sequence_info.name),
+        ));
+    }
+    if range_only && request.signed_range.is_some() {
+        return Err(RequestError::new(
+            StatusCode::BAD_REQUEST,
+            format!("{} is only defined over a range and can't be given a signed range", sequence_info.name),
+        ));
+    }
+    let count = match (&request.indices, request.signed_range) {
+        (Some(indices), _) => indices.len() as u64,
+        (None, Some(range)) => range.count(),
+        (None, None) => request.range.count(),
+    };
+    if count > config.max_range_len {
+        return Err(RequestError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Range has {} elements, at most {} allowed", count, config.max_range_len),
+        ));
+    }
+    // Building and evaluating both recurse once per level, so a deep enough
+    // tree would overflow the stack.
+    let depth = depth(&request.sequences);
+    if depth > config.max_depth {
+        return Err(RequestError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Sequences are nested {} deep, at most {} allowed", depth, config.max_depth),
+        ));
+    }
+    if let Err(message) = check_finite(&request.parameters, &request.sequences, &sequence_info.name) {
+        return Err(RequestError::new(StatusCode::BAD_REQUEST, message));
+    }
+    validate_syntax(&sequence_info.name, &request.parameters, &request.sequences, &sequence_info.name)?;
+    // We can't mix our sequences with a peer's, so if any sequence in the
+    // tree isn't ours the whole request is delegated.
+    match build_sequence(&sequence_info.name, &request.parameters, &request.sequences) {
+        Ok(seq) => {
+            let last = match (&request.indices, request.signed_range) {
+                (Some(indices), _) => indices.iter().max().copied().unwrap_or(0),
+                (None, Some(range)) => range.to.max(0) as u64,
+                (None, None) => request.range.to,
+            };
+            if let Some(name) = from_zero(&sequence_info.name, &request.sequences).filter(|_| last >= config.max_range_len) {
+                return Err(RequestError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("{} computes every element from index 0, so the range must end below {}", name, config.max_range_len),
+                ));
+            }
+            Ok(Some(seq))
+        }
+        Err(BuildError::UnknownSequence(name)) => {
+            debug!(%name, "not one of our sequences");
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// First sequence in the tree that computes every element from index 0 on.
+fn from_zero(name: &str, sequences: &[Box<SequenceSyntax>]) -> Option<&'static str> {
+    if let Some(entry) = REGISTRY.iter().find(|entry| entry.name == name && entry.from_zero) {
+        return Some(entry.name);
+    }
+    sequences.iter().find_map(|seq| from_zero(&seq.name, &seq.sequences))
+}
+
+// Builds the tree bottom up. The counts have already been checked by
+// validate_syntax, which the constructors rely on.
+fn build_sequence(name: &str, params: &[f64], sequences: &[Box<SequenceSyntax>]) -> Result<Box<dyn Sequence>, BuildError> {
+    let Some(entry) = REGISTRY.iter().find(|entry| entry.name == name) else {
+        return Err(BuildError::UnknownSequence(name.to_string()));
+    };
+    let subs = sequences
+        .iter()
+        .map(|seq| build_sequence(&seq.name, &seq.parameters, &seq.sequences))
+        .collect::<Result<Vec<_>, _>>()?;
+    (entry.build)(params, subs).map_err(BuildError::InvalidParameters)
+}
+
+#[tracing::instrument(skip(req, sequence_info, state), fields(sequence = %sequence_info.name))]
+async fn handle_validate_request(req: Request<Incoming>, sequence_info: &SequenceInfo, state: &State) -> Result<Response<BoxBody<Bytes, Error>>, hyper::Error> {
+    let body = match collect_body(req, state.config.body_limit).await {
+        Ok(body) => body,
+        Err(e) => return Ok(error_response(e)),
+    };
+    let validation = match serde_json::from_str::<SequenceRequest>(&body) {
+        Err(e) => Validation { valid: false, error: Some(format!("Invalid request: {}", e)) },
+        Ok(request) => match build(&request, sequence_info, &state.config) {
+            Ok(Some(_)) => Validation { valid: true, error: None },
+            Ok(None) => Validation { valid: false, error: Some(format!("{} needs sequences only a peer has", sequence_info.name)) },
+            Err(e) => Validation { valid: false, error: Some(e.message) },
+        },
+    };
+    Ok(Response::new(full(serde_json::to_string(&validation).unwrap())))
+}
+
+// On a blocking thread the computation can't stall other requests and the
+// timeout can fire. A timed out computation is cancelled, and keeps its permit
+// only until its loops notice.
+async fn run_blocking<T: Send + 'static>(state: &State, f: impl FnOnce() -> T + Send + 'static) -> Result<T, RequestError> {
+    let Ok(permit) = state.evaluations.clone().try_acquire_owned() else {
+        return Err(RequestError::new(StatusCode::SERVICE_UNAVAILABLE, "Too many sequences being generated, try again later"));
+    };
+    let cancel = Arc::new(AtomicBool::new(false));
+    let flag = cancel.clone();
+    let evaluation = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        CANCELLED.with(|cancelled| *cancelled.borrow_mut() = Some(flag));
+        let result = f();
+        CANCELLED.with(|cancelled| *cancelled.borrow_mut() = None);
+        result
+    });
+    let result = tokio::time::timeout(state.config.evaluation_timeout, evaluation).await;
+    if result.is_err() {
+        cancel.store(true, Ordering::Relaxed);
+    }
+    match result {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(e)) => {
+            error!("Failed to generate sequence: {}", e);
+            Err(RequestError::new(StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate sequence"))
+        }
+        Err(_) => Err(RequestError::new(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!("Generating the sequence took longer than {} seconds", state.config.evaluation_timeout.as_secs()),
+        )),
+    }
+}
+
+// A sequence appears to converge if, from some index on, every successive
+// difference among the given terms is below the tolerance. The last term is
+// then the estimated limit.
+fn estimate_limit(values: &[f64], tolerance: f64) -> Limit {
+    if values.iter().any(|x| !x.is_finite()) {
+        return Limit { converges: false, limit: None, settled_at: None };
+    }
+    let unsettled = values.windows(2).rposition(|pair| (pair[1] - pair[0]).abs() >= tolerance);
+    let settled_at = unsettled.map_or(0, |i| i + 1);
+    if settled_at + 1 >= values.len() {
+        return Limit { converges: false, limit: None, settled_at: None };
+    }
+    Limit { converges: true, limit: values.last().copied(), settled_at: Some(settled_at as u64) }
+}
+
+async fn handle_limit_request(req: Request<Incoming>, sequence_info: &SequenceInfo, state: &State) -> Result<Response<BoxBody<Bytes, Error>>, hyper::Error> {
+    let body = match collect_body(req, state.config.body_limit).await {
+        Ok(body) => body,
+        Err(e) => return Ok(error_response(e)),
+    };
+    let request = match serde_json::from_str::<LimitRequest>(&body) {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(RequestError::new(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)))),
+    };
+    if request.tolerance <= 0.0 {
+        return Ok(error_response(RequestError::new(StatusCode::BAD_REQUEST, "Tolerance must be positive")));
+    }
+    if request.max_iterations < 2 || request.max_iterations > state.config.max_range_len {
+        return Ok(error_response(RequestError::new(
+            StatusCode::BAD_REQUEST,
+            format!("max_iterations must be between 2 and {}", state.config.max_range_len),
+        )));
+    }
+    METRICS.count_request(&sequence_info.name);
+
+    let seq = match build_local(request.sequence, sequence_info, state) {
+        Ok(seq) => seq,
+        Err(e) => return Ok(error_response(e)),
+    };
+    let range = Range { from: 0, to: request.max_iterations - 1, step: 1 };
+    let tolerance = request.tolerance;
+    match run_blocking(state, move || estimate_limit(&seq.range(range), tolerance)).await {
+        Ok(limit) => Ok(Response::new(full(serde_json::to_string(&limit).unwrap()))),
+        Err(e) => Ok(error_response(e)),
+    }
+}
+
+// Smallest p such that every term repeats p terms later, to within the
+// tolerance. The window has to hold the period at least twice.
+fn detect_period(values: &[f64], tolerance: f64) -> Option<u64> {
+    (1..=values.len() / 2)
+        .take_while(|_| !cancelled())
+        .find(|&p| values.iter().zip(&values[p..]).all(|(a, b)| (a - b).abs() <= tolerance))
+        .map(|p| p as u64)
+}
+
+async fn handle_period_request(req: Request<Incoming>, sequence_info: &SequenceInfo, state: &State) -> Result<Response<BoxBody<Bytes, Error>>, hyper::Error> {
+    let body = match collect_body(req, state.config.body_limit).await {
+        Ok(body) => body,
+        Err(e) => return Ok(error_response(e)),
+    };
+    let request = match serde_json::from_str::<PeriodRequest>(&body) {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(RequestError::new(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)))),
+    };
+    if request.tolerance < 0.0 {
+        return Ok(error_response(RequestError::new(StatusCode::BAD_REQUEST, "Tolerance can't be negative")));
+    }
+    let max_window = state.config.max_range_len.min(MAX_PERIOD_WINDOW);
+    if request.window < 2 || request.window > max_window {
+        return Ok(error_response(RequestError::new(
+            StatusCode::BAD_REQUEST,
+            format!("window must be between 2 and {}", max_window),
+        )));
+    }
+    METRICS.count_request(&sequence_info.name);
+
+    let seq = match build_local(request.sequence, sequence_info, state) {
+        Ok(seq) => seq,
+        Err(e) => return Ok(error_response(e)),
+    };
+    let range = Range { from: 0, to: request.window - 1, step: 1 };
+    let tolerance = request.tolerance;
+    match run_blocking(state, move || Period { period: detect_period(&seq.range(range), tolerance) }).await {
+        Ok(period) => Ok(Response::new(full(serde_json::to_string(&period).unwrap()))),
+        Err(e) => Ok(error_response(e)),
+    }
+}
+
+// What POST /sequence/{name}/{analysis} asks for instead of the elements.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Analysis {
+    Validate,
+    Limit,
+    Period,
+}
+
+impl Analysis {
+    // Takes exactly one analysis off the end of the path.
+    fn split(path: &str) -> Option<(&str, Analysis)> {
+        let (sequence, analysis) = path.rsplit_once('/')?;
+        let analysis = match analysis {
+            "validate" => Analysis::Validate,
+            "limit" => Analysis::Limit,
+            "period" => Analysis::Period,
+            _ => return None,
+        };
+        Some((sequence, analysis))
+    }
+}
+
+async fn handle_analysis_request(req: Request<Incoming>, path: &str, analysis: Analysis, state: &State) -> Result<Response<BoxBody<Bytes, Error>>, hyper::Error> {
+    let seqs = sequences();
+    let Some(sequence_info) = find_sequence(&seqs, path) else {
+        return Ok(unknown_sequence_response(path));
+    };
+    match analysis {
+        Analysis::Validate => handle_validate_request(req, sequence_info, state).await,
+        Analysis::Limit => handle_limit_request(req, sequence_info, state).await,
+        Analysis::Period => handle_period_request(req, sequence_info, state).await,
+    }
+}
+
+// For analyses that need the terms themselves, so they can't be delegated.
+fn build_local(sequence: SequenceArguments, sequence_info: &SequenceInfo, state: &State) -> Result<Box<dyn Sequence>, RequestError> {
+    let request = SequenceRequest {
+        range: Range { from: 0, to: 0, step: 1 },
+        parameters: sequence.parameters,
+        sequences: sequence.sequences,
+        request_id: None,
+        peers: None,
+        signed_range: None,
+        indices: None,
+    };
+    match build(&request, sequence_info, &state.config)? {
+        Some(seq) => Ok(seq),
+        None => Err(RequestError::new(
+            StatusCode::NOT_FOUND,
+            format!("{} needs sequences only a peer has", sequence_info.name),
+        )),
+    }
+}
+
+// Builds the sequence locally if we can, otherwise delegates it to a peer.
+// Returns the requested range as JSON in the given format.
+async fn evaluate(request: SequenceRequest, sequence_info: &SequenceInfo, state: &State, format: Format) -> Result<String, RequestError> {
+    METRICS.count_request(&sequence_info.name);
+
+    match build(&request, sequence_info, &state.config)? {
+        Some(seq) => {
+            // Serializing a long range is just as much work, so it happens on
+            // the blocking thread too.
+            let (range, signed_range, indices) = (request.range, request.signed_range, request.indices);
+            let result = run_blocking(state, move || {
+                let values = match (indices, signed_range) {
+                    // One element ranges rather than k_th, so where a
+                    // TakeUntil below has already ended the element is NaN,
+                    // sent as null. Range-only roots were rejected in build.
+                    (Some(indices), _) => indices
+                        .into_iter()
+                        .map(|k| seq.range(Range { from: k, to: k, step: 1 }).first().copied().unwrap_or(f64::NAN))
+                        .collect(),
+                    (None, Some(signed_range)) => seq.range_signed(signed_range),
+                    (None, None) => seq.range(range),
+                };
+                format.render(values)
+            }).await?;
+            debug!(%result, "generated sequence");
+            Ok(result)
+        },
+        None if state.config.no_delegate => Err(RequestError::new(
+            StatusCode::NOT_FOUND,
+            format!("{} is not available and delegation is disabled", sequence_info.name),
+        )),
+        None if request.signed_range.is_some() || request.indices.is_some() => Err(RequestError::new(
+            StatusCode::BAD_REQUEST,
+            format!("{} is not available here and signed ranges or index lists can't be delegated", sequence_info.name),
+        )),
+        None => match delegate(request, &sequence_info.name, state).await? {
+            Some(result) if format.is_default() => {
+                debug!(%result, "delegated sequence");
+                Ok(result)
+            }
+            Some(result) => {
+                debug!(%result, "delegated sequence");
+                let name = sequence_info.name.clone();
+                run_blocking(state, move || match serde_json::from_str::<Vec<Option<f64>>>(&result) {
+                    Ok(values) => Ok(format.render(values.into_iter().map(|x| x.unwrap_or(f64::NAN)).collect())),
+                    Err(_) => Err(RequestError::new(StatusCode::BAD_GATEWAY, format!("Peer sent invalid values for {}", name))),
+                }).await?
+            }
+            None => Err(RequestError::new(StatusCode::NOT_FOUND, format!("Nobody has {}", sequence_info.name))),
+        },
+    }
+}
+
+// Number of levels in a tree with the given sub-sequences at its root, so a
+// lone sequence is 1 deep.
+fn depth(sequences: &[Box<SequenceSyntax>]) -> usize {
+    1 + sequences.iter().map(|seq| depth(&seq.sequences)).max().unwrap_or(0)
+}
+
+// Unlike validate_syntax this also walks nodes we don't know, since a NaN or
+// infinite parameter is wrong for any sequence.
+fn check_finite(parameters: &[f64], sequences: &[Box<SequenceSyntax>], path: &str) -> Result<(), String> {
+    if let Some(i) = parameters.iter().position(|x| !x.is_finite()) {
+        return Err(format!("{}: parameter {} is not a finite number", path, i));
+    }
+    for (i, seq) in sequences.iter().enumerate() {
+        check_finite(&seq.parameters, &seq.sequences, &format!("{}.sequences[{}]", path, i))?;
+    }
+    Ok(())
+}
+
+// Checks every node we know against its registration and reports the path of
+// the first mismatch, including range-only sequences whose parent would call
+// their k_th. Nodes we don't know are left for delegation.
+fn validate_syntax(name: &str, parameters: &[f64], sequences: &[Box<SequenceSyntax>], path: &str) -> Result<(), BuildError> {
+    let Some(entry) = REGISTRY.iter().find(|entry| entry.name == name) else {
+        return Ok(());
+    };
+
+    let expected = entry.parameters.len();
+    if parameters.len() < expected || (parameters.len() > expected && !entry.variable_parameters) {
+        return Err(BuildError::WrongParamCount {
+            path: path.to_string(),
+            name: name.to_string(),
+            expected,
+            at_least: entry.variable_parameters,
+            got: parameters.len(),
+        });
+    }
+
+    let expected = entry.sequences as usize;
+    if sequences.len() != expected {
+        return Err(BuildError::WrongChildCount { path: path.to_string(), name: name.to_string(), expected, got: sequences.len() });
+    }
+
+    for (i, seq) in sequences.iter().enumerate() {
+        let path = format!("{}.sequences[{}]", path, i);
+        if !entry.reads_ranges && REGISTRY.iter().any(|child| child.name == seq.name && child.range_only) {
+            return Err(BuildError::RangeOnly { path, name: seq.name.clone(), parent: name.to_string() });
+        }
+        validate_syntax(&seq.name, &seq.parameters, &seq.sequences, &path)?;
+    }
+    Ok(())
+}
+
+#[tracing::instrument(skip(request, state))]
+// Returns None when no peer advertises the sequence, and an error when the
+// registry can't be reached (503) or the peer that has it fails (502).
+async fn delegate(mut request: SequenceRequest, sequence_name: &str, state: &State) -> Result<Option<String>, RequestError> {
+    // Pinned peers are only for this hop, so they aren't forwarded.
+    let projects: serde_json::Value = match request.peers.take() {
+        Some(peers) => serde_json::to_value(peers).unwrap(),
+        None => {
+            let b = send_get(state.config.projects_url()).await.map_err(|e| {
+                warn!("Registry unreachable: {}", e);
+                RequestError::new(StatusCode::SERVICE_UNAVAILABLE, "Registry unreachable, can't delegate the request")
+            })?;
+            let projects: serde_json::Value = serde_json::from_str(&b).unwrap_or(serde_json::Value::Null);
+            if !projects.is_array() {
+                warn!("Registry sent something other than a list of projects: {}", b);
+                return Err(RequestError::new(StatusCode::BAD_GATEWAY, "Registry sent an invalid list of projects"));
+            }
+            state.status.lock().unwrap().peers = count_peers(&projects);
+            projects
+        }
+    };
+    let length = projects.as_array().map_or(0, |projects| projects.len());
+
+    let body = serde_json::to_string(&request).map_err(|e| {
+        error!("Failed to serialize the request for {}: {}", sequence_name, e);
+        RequestError::new(StatusCode::INTERNAL_SERVER_ERROR, "Failed to forward the request")
+    })?;
+
+    if length > 0 {
+        let mut vec: Vec<usize> = (0..length).collect();
+        vec.shuffle(&mut thread_rng());
+        if state.config.prefer_fastest_peer {
+            // Only the peers we would ask anyway are pinged, and then asked
+            // fastest first. The sort is stable, so peers that are equally
+            // fast stay shuffled.
+            let candidates: Vec<usize> = vec
+                .into_iter()
+                .filter(|&i| !is_self(&projects[i]))
+                .take(state.config.max_delegation_peers)
+                .collect();
+            let addresses: Vec<String> = candidates
+                .iter()
+                .map(|&i| format!("{}:{}", projects[i]["ip"].to_string().replace("\"", ""), projects[i]["port"].to_string().replace("\"", "")))
+                .collect();
+            let latencies = peer_latencies(&addresses, state).await;
+            let mut ordered: Vec<(usize, Duration)> = candidates.into_iter().zip(latencies).collect();
+            ordered.sort_by_key(|&(_, latency)| latency);
+            vec = ordered.into_iter().map(|(i, _)| i).collect();
+        }
+
+        let mut tried = 0;
+        for i in vec.iter() {
+            if tried == state.config.max_delegation_peers {
+                warn!("Gave up on {} after asking {} peers", sequence_name, tried);
+                return Ok(None);
+            }
+            let ip = &projects[i]["ip"].to_string().replace("\"", "");
+            let port = projects[i]["port"].to_string().replace("\"", "");
+            debug!("ip:port = {}:{}", &ip, &port);
+
+            if !is_self(&projects[i]) {
+                tried += 1;
+                let url = format!("http://{}:{}/sequence", ip, port);
+                let data = match send_get(url.clone()).await {
+                    Ok(resp) => resp,
+                    Err(e) => {
+                        warn!("Failed to list sequences of {}:{}: {}", ip, port, e);
+                        continue;
+                    }
+                };
+
+                let seqs: serde_json::Value = serde_json::from_str(&data).unwrap_or(serde_json::Value::Null);
+                for i in 0..seqs.as_array().map_or(0, |seqs| seqs.len()) {
+                    if seqs[i]["name"].as_str() == Some(sequence_name) {
+                        let url_of_seq = format!("{}/{}", &url, sequence_name);
+                        METRICS.delegations.fetch_add(1, Ordering::Relaxed);
+                        return match send_post(url_of_seq, body).await {
+                            Ok(resp) => Ok(Some(resp)),
+                            Err(e) => {
+                                METRICS.delegation_failures.fetch_add(1, Ordering::Relaxed);
+                                warn!("Peer failed to generate {}: {}", sequence_name, e);
+                                Err(RequestError::new(
+                                    StatusCode::BAD_GATEWAY,
+                                    format!("{}:{} failed to generate {}", ip, port, sequence_name),
+                                ))
+                            },
+                        };
+                    };
+                }
+            }
+        }
+        warn!("Nobody has {}", sequence_name);
+        Ok(None)
+    } else {
+        warn!("Nobody has {}", sequence_name);
+        Ok(None)
+    }
+}
+
+async fn register(state: &State) -> Result<(), reqwest::Error> {
+    let b = send_get(state.config.projects_url()).await?;
+    debug!("Registry projects: {}", b);
+
+    let b = send_post(
+        state.config.projects_url(),
+        serde_json::to_string(&get_project()).unwrap(),
+    ).await?;
+    info!("Registered with registry: {}", b);
+    {
+        let mut status = state.status.lock().unwrap();
+        status.registered = true;
+        status.ever_registered = true;
+    }
+
+    let b = send_get(state.config.projects_url()).await?;
+    debug!("Registry projects: {}", b);
+    if let Ok(projects) = serde_json::from_str::<serde_json::Value>(&b) {
+        state.status.lock().unwrap().peers = count_peers(&projects);
+    }
+    Ok(())
+}
+
+async fn heartbeat(state: Shared) {
+    let mut ticker = tokio::time::interval(state.config.heartbeat_interval);
+    // The first tick completes immediately and main has just tried to register.
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let registered = match send_post(
+            state.config.projects_url(),
+            serde_json::to_string(&get_project()).unwrap(),
+        ).await {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Failed to re-register with registry: {}", e);
+                false
+            }
+        };
+        let mut status = state.status.lock().unwrap();
+        status.registered = registered;
+        status.ever_registered |= registered;
+    }
+}
+
+// Registers and starts the heartbeat, unless delegation is disabled, in which
+// case the registry is never contacted.
+async fn join_registry(state: &Shared) {
+    if state.config.no_delegate {
+        info!("Delegation disabled, not registering with registry");
+    } else {
+        // Without a registry we still serve our own sequences; the heartbeat
+        // keeps trying to register.
+        match tokio::time::timeout(state.config.registry_timeout, register(state)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to register with registry: {}", e),
+            Err(_) => warn!("Registry did not answer within {:?}", state.config.registry_timeout),
+        }
+
+        tokio::task::spawn(heartbeat(state.clone()));
+    }
+}
+
+// Sequences are built and evaluated recursively, at least one call per level
+// of the tree, so the threads get more than tokio's default 2 MiB of stack.
+// JSON deeper than serde_json's recursion limit is already rejected while
+// parsing, which keeps trees below 64 levels even if MAX_DEPTH is raised.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_stack_size(THREAD_STACK_SIZE)
+        .build()?
+        .block_on(serve())
+}
+
+async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+
+    let file = match config_path() {
+        Some(path) => ConfigFile::read(&path)?,
+        None => ConfigFile::default(),
+    };
+    let config = Config::load(file)?;
+    debug!(?config, "loaded config");
+    let addr: SocketAddr = ([127, 0, 0, 1], PORT).into();
+    let state: Shared = Arc::new(State::new(config));
+
+    join_registry(&state).await;
+
+    let listener = TcpListener::bind(addr).await?;
+    info!("Listening on http://{}", addr);
+
+    loop {
+        let (stream, client) = listener.accept().await?;
+        tokio::task::spawn(serve_connection(stream, client, state.clone()));
+    }
+}
+
+// Answers one request from client.
+async fn handle(req: Request<Incoming>, client: SocketAddr, state: &State) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
+    if let Err(wait) = state.take_token(client.ip()) {
+        let mut response = error_response(RequestError::new(StatusCode::TOO_MANY_REQUESTS, "Too many requests, slow down"));
+        response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(wait.as_secs_f64().ceil() as u64));
+        add_cors_headers(&mut response, &state.config.cors_origin);
+        return Ok(response);
+    }
+    // HEAD is answered like GET, and the body dropped below.
+    let head = req.method() == Method::HEAD;
+    let method = if head { Method::GET } else { req.method().clone() };
+    // A trailing slash names the same resource.
+    let path = req.uri().path().trim_end_matches('/').to_string();
+    let response = match (&method, path.as_str()) {
+        // CORS preflight; the headers themselves are added below.
+        (&Method::OPTIONS, _) => {
+            let mut preflight = Response::new(empty());
+            *preflight.status_mut() = StatusCode::NO_CONTENT;
+            Ok(preflight)
+        }
+        (&Method::GET, "/ping") => Ok::<_, Error>(Response::new(full(
+            serde_json::to_string(&get_project()).unwrap(),
+        ))),
+        (&Method::GET, "/ping/registry") => Ok(Response::new(full(
+            serde_json::to_string(&ping_registry(state).await).unwrap(),
+        ))),
+        (&Method::GET, "/schema") => Ok(Response::new(full(
+            serde_json::to_string(&schemars::schema_for!(SequenceRequest)).unwrap(),
+        ))),
+        (&Method::GET, "/metrics") => Ok(Response::builder()
+            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(full(METRICS.render()))
+            .unwrap()),
+        (&Method::GET, "/version") => Ok(Response::new(full(
+            serde_json::to_string(&get_version()).unwrap(),
+        ))),
+        (&Method::GET, "/capabilities") => Ok(Response::new(full(
+            serde_json::to_string(&get_capabilities(&state.config)).unwrap(),
+        ))),
+        (&Method::GET, "/health") => Ok(Response::new(full(
+            serde_json::to_string(&get_health(state)).unwrap(),
+        ))),
+        (&Method::GET, "/ready") => if is_ready(state) {
+            Ok(Response::new(full(serde_json::to_string(&get_health(state)).unwrap())))
+        } else {
+            Ok(error_response(RequestError::new(StatusCode::SERVICE_UNAVAILABLE, "Not registered with the registry yet")))
+        },
+        (&Method::GET, "/peers") => match get_peers(state).await {
+            Ok(peers) => Ok(Response::new(full(serde_json::to_string(&peers).unwrap()))),
+            Err(e) => {
+                warn!("Registry unreachable: {}", e);
+                Ok(error_response(RequestError::new(StatusCode::SERVICE_UNAVAILABLE, "Registry unreachable")))
+            }
+        },
+        (&Method::GET, "/sequence") => {
+            let sequences = sequences();
+            let data = serde_json::to_string(&sequences).unwrap();
+            Ok(Response::new(full(data,
+        )))
+        }
+        (&Method::GET, r) => {
+            let seqs = sequences();
+            if let Some(sequence_info) = find_sequence(&seqs, r) {
+                let data = serde_json::to_string(sequence_info).unwrap();
+                Ok(Response::new(full(data)))
+            } else {
+                Ok(unknown_sequence_response(r))
+            }
+        }
+        (&Method::POST, "/sequence/batch") => handle_batch_request(req, state).await,
+        (&Method::POST, "/sequence/compare") => handle_compare_request(req, state).await,
+        (&Method::POST, "/evaluate") => handle_evaluate_request(req, state).await,
+        (&Method::POST, "/parse") => handle_parse_request(req, state).await,
+        (&Method::POST, r) if let Some((r, analysis)) = Analysis::split(r) => handle_analysis_request(req, r, analysis, state).await,
+        (&Method::POST, r) => {
+            let seqs = sequences();
+            if let Some(sequence_info) = find_sequence(&seqs, r) {
+                handle_sequence_request(req, sequence_info, state).await
+            } else if let Some(name) = r.strip_prefix("/sequence/").filter(|name| !name.is_empty() && !name.contains('/')) {
+                // A peer might still have it, so this goes to delegation.
+                let sequence_info = unknown_sequence(name);
+                handle_sequence_request(req, &sequence_info, state).await
+            } else {
+                Ok(not_found())
+            }
+        }
+        _ => Ok(not_found()),
+    };
+    let mut response = response?;
+    add_cors_headers(&mut response, &state.config.cors_origin);
+    if head {
+        response = without_body(response).await?;
+    }
+    Ok(response)
+}
+
+// Serves the requests of one connection until the client closes it or it has
+// been idle for connection_timeout.
+async fn serve_connection(stream: TcpStream, client: SocketAddr, state: Shared) {
+    let io = TokioIo::new(stream);
+    let http2 = state.config.http2;
+    let header_read_timeout = state.config.header_read_timeout;
+    let keep_alive = state.config.keep_alive;
+    let idle = IdleTimer::new(state.config.connection_timeout);
+    let timer = idle.clone();
+    let service = service_fn(move |req| {
+        let state = state.clone();
+        let busy = timer.busy();
+        async move {
+            let _busy = busy;
+            handle(req, client, &state).await
+        }
+    });
+
+    // The auto builder also accepts HTTP/2 with prior knowledge (h2c).
+    // A connection without a request for connection_timeout is closed,
+    // so idle keep-alive connections don't pile up.
+    let served: Result<(), Box<dyn std::error::Error + Send + Sync>> = if http2 {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(header_read_timeout)
+            .keep_alive(keep_alive);
+        let connection = builder.serve_connection(io, service);
+        tokio::pin!(connection);
+        tokio::select! {
+            served = connection.as_mut() => served,
+            _ = idle.expired() => {
+                connection.as_mut().graceful_shutdown();
+                connection.await
+            }
+        }
+    } else {
+        let connection = http1::Builder::new()
+            .timer(TokioTimer::new())
+            .header_read_timeout(header_read_timeout)
+            .keep_alive(keep_alive)
+            .serve_connection(io, service);
+        tokio::pin!(connection);
+        tokio::select! {
+            served = connection.as_mut() => served,
+            _ = idle.expired() => {
+                connection.as_mut().graceful_shutdown();
+                connection.await
+            }
+        }
+        .map_err(|e| e.into())
+    };
+    if let Err(err) = served {
+        warn!("Error serving connection: {:?}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Settings as if nothing was configured, except that nothing is delegated.
+    fn config() -> Config {
+        Config::load(ConfigFile { no_delegate: Some(true), ..ConfigFile::default() }).unwrap()
+    }
+
+    // Starts a generator with the given settings on a free port and returns
+    // its base URL.
+    async fn generator(config: Config) -> String {
+        serving(Arc::new(State::new(config))).await
+    }
+
+    // Like generator, for tests that also need to reach the state.
+    async fn serving(state: Shared) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, client) = listener.accept().await.unwrap();
+                tokio::spawn(serve_connection(stream, client, state.clone()));
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    // Server on a free port that answers each request with
+    // handler(method, path, body) after waiting delay, standing in for a
+    // registry or a peer.
+    async fn stub_with_delay<F>(delay: Duration, handler: F) -> SocketAddr
+    where
+        F: Fn(&Method, &str, String) -> (StatusCode, String) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handler = Arc::new(handler);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req: Request<Incoming>| {
+                        let handler = handler.clone();
+                        async move {
+                            let (method, path) = (req.method().clone(), req.uri().path().to_string());
+                            let body = req.into_body().collect().await?.to_bytes();
+                            tokio::time::sleep(delay).await;
+                            let (status, body) = handler(&method, &path, String::from_utf8_lossy(&body).to_string());
+                            let mut response = Response::new(full(body));
+                            *response.status_mut() = status;
+                            Ok::<_, Error>(response)
+                        }
+                    });
+                    let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await;
+                });
+            }
+        });
+        addr
+    }
+
+    async fn stub<F>(handler: F) -> SocketAddr
+    where
+        F: Fn(&Method, &str, String) -> (StatusCode, String) + Send + Sync + 'static,
+    {
+        stub_with_delay(Duration::ZERO, handler).await
+    }
+
+    // Registry that lists the given projects on GET /project and adds the
+    // ones registered with POST /project.
+    async fn registry(projects: Vec<serde_json::Value>) -> (SocketAddr, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let projects = Arc::new(Mutex::new(projects));
+        let listed = projects.clone();
+        let addr = stub(move |method, path, body| match (method, path) {
+            (&Method::GET, "/project") => (StatusCode::OK, serde_json::to_string(&*listed.lock().unwrap()).unwrap()),
+            (&Method::POST, "/project") => match serde_json::from_str(&body) {
+                Ok(project) => {
+                    listed.lock().unwrap().push(project);
+                    (StatusCode::OK, body)
+                }
+                Err(_) => (StatusCode::BAD_REQUEST, String::new()),
+            },
+            _ => (StatusCode::NOT_FOUND, String::new()),
+        })
+        .await;
+        (addr, projects)
+    }
+
+    // Peer whose only sequence is name, always answered with values.
+    async fn peer(name: &'static str, values: &'static str, delay: Duration) -> SocketAddr {
+        stub_with_delay(delay, move |method, path, _| match (method, path) {
+            (&Method::GET, "/ping") => (StatusCode::OK, "{}".to_string()),
+            (&Method::GET, "/sequence") => (StatusCode::OK, format!(r#"[{{"name":"{}","description":"","parameters":0,"sequences":0}}]"#, name)),
+            (&Method::POST, path) if path == format!("/sequence/{}", name) => (StatusCode::OK, values.to_string()),
+            _ => (StatusCode::NOT_FOUND, String::new()),
+        })
+        .await
+    }
+
+    fn project(addr: SocketAddr) -> serde_json::Value {
+        serde_json::json!({ "name": "peer", "ip": addr.ip().to_string(), "port": addr.port() })
+    }
+
+    fn delegating(registry: SocketAddr) -> Config {
+        Config {
+            no_delegate: false,
+            registry_url: format!("http://{}", registry),
+            ..config()
+        }
+    }
+
+    async fn post(url: &str, body: &str) -> (u16, String) {
+        let response = reqwest::Client::new().post(url).body(body.to_string()).send().await.unwrap();
+        (response.status().as_u16(), response.text().await.unwrap())
+    }
+
+    async fn get(url: &str) -> (u16, String) {
+        let response = reqwest::get(url).await.unwrap();
+        (response.status().as_u16(), response.text().await.unwrap())
+    }
+
+    fn node(name: &str, parameters: &[f64], sequences: Vec<SequenceSyntax>) -> SequenceSyntax {
+        SequenceSyntax {
+            name: name.to_string(),
+            parameters: parameters.to_vec(),
+            sequences: sequences.into_iter().map(Box::new).collect(),
+        }
+    }
+
+    // Asks the generator at url for elements from to to of the tree.
+    async fn request(url: &str, syntax: &SequenceSyntax, from: u64, to: u64) -> (u16, String) {
+        post(&format!("{}/sequence/{}", url, syntax.name), &request_body(syntax, from, to)).await
+    }
+
+    // Body of a request for elements from to to of the tree, whose root is
+    // named in the path.
+    fn request_body(syntax: &SequenceSyntax, from: u64, to: u64) -> String {
+        serde_json::json!({
+            "range": { "from": from, "to": to, "step": 1 },
+            "parameters": syntax.parameters,
+            "sequences": syntax.sequences,
+        })
+        .to_string()
+    }
+
+    // Index under levels - 1 Negates, so a tree levels deep.
+    fn nested(levels: usize) -> SequenceSyntax {
+        (1..levels).fold(node("Index", &[], vec![]), |seq, _| node("Negate", &[], vec![seq]))
+    }
+
+    // Sends request as is and returns the whole response, for requests reqwest
+    // won't make.
+    async fn raw(url: &str, request: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let mut stream = tokio::net::TcpStream::connect(url.trim_start_matches("http://")).await.unwrap();
+        // The generator may answer and close before it has read everything.
+        let _ = stream.write_all(request.as_bytes()).await;
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        String::from_utf8_lossy(&response).to_string()
+    }
+
+    fn assert_close(values: &[f64], expected: &[f64]) {
+        assert_eq!(values.len(), expected.len(), "{:?} != {:?}", values, expected);
+        for (x, y) in values.iter().zip(expected) {
+            assert!((x - y).abs() < 1e-9, "{:?} != {:?}", values, expected);
+        }
+    }
+
+    // Elements 0 to n - 1.
+    fn first(seq: Box<dyn Sequence>, n: u64) -> Vec<f64> {
+        seq.range(Range { from: 0, to: n - 1, step: 1 })
+    }
+
+    #[tokio::test]
+    async fn describes_a_single_sequence() {
+        let url = generator(config()).await;
+        let (status, body) = get(&format!("{}/sequence/Geometric", url)).await;
+        assert_eq!(status, 200);
+        let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(info["name"], "Geometric");
+        assert_eq!(info["parameters"], 2);
+        assert_eq!(info["sequences"], 0);
+    }
+
+    #[tokio::test]
+    async fn describing_an_unknown_sequence_is_404() {
+        let url = generator(config()).await;
+        assert_eq!(get(&format!("{}/sequence/Nope", url)).await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn health_right_after_startup() {
+        let url = generator(config()).await;
+        let (status, body) = get(&format!("{}/health", url)).await;
+        assert_eq!(status, 200);
+        let health: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(health["uptime_secs"], 0);
+        assert_eq!(health["registered"], false);
+        assert_eq!(health["port"], PORT);
+        assert_eq!(health["peers"], 0);
+    }
+
+    #[test]
+    fn settings_fall_back_to_their_defaults() {
+        assert_eq!(env_or("GENERATOR_TEST_UNSET", 30), Ok(30));
+        std::env::set_var("GENERATOR_TEST_GARBAGE", "soon");
+        assert_eq!(env_or("GENERATOR_TEST_GARBAGE", 30), Err("Invalid value \"soon\" for GENERATOR_TEST_GARBAGE".to_string()));
+        std::env::set_var("GENERATOR_TEST_SET", "5");
+        assert_eq!(env_or("GENERATOR_TEST_SET", 30), Ok(5));
+    }
+
+    #[test]
+    fn reverse_turns_the_range_around() {
+        let reverse = Reverse::new(Arithmetic::new(0.0, 1.0));
+        assert_eq!(reverse.range(Range { from: 2, to: 5, step: 1 }), vec![5.0, 4.0, 3.0, 2.0]);
+    }
+
+    #[test]
+    fn interleave_alternates_between_its_sequences() {
+        let interleave = Interleave::new(Constant::new(0.0), Constant::new(1.0));
+        assert_eq!(first(interleave, 6), [0.0, 1.0, 0.0, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn interleave_reindexes_both_sequences() {
+        let interleave = Interleave::new(Arithmetic::new(0.0, 1.0), Arithmetic::new(10.0, 1.0));
+        assert_eq!(first(interleave, 5), [0.0, 10.0, 1.0, 11.0, 2.0]);
+    }
+
+    #[test]
+    fn clamp_stops_a_growing_sequence_at_the_bounds() {
+        let clamp = Clamp::new(0.0, 3.0, Arithmetic::new(-2.0, 1.0));
+        assert_eq!(first(clamp, 8), [0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 3.0, 3.0]);
+    }
+
+    #[test]
+    fn clamp_with_bounds_the_wrong_way_round_does_not_panic() {
+        assert_eq!(first(Clamp::new(3.0, 1.0, Arithmetic::new(0.0, 1.0)), 2), [1.0, 1.0]);
+    }
+
+    #[test]
+    fn abs_of_alternating_recovers_the_magnitudes() {
+        assert_eq!(first(Alternating::new(Constant::new(5.0)), 4), [5.0, -5.0, 5.0, -5.0]);
+        assert_eq!(first(Abs::new(Alternating::new(Constant::new(5.0))), 4), [5.0; 4]);
+    }
+
+    #[test]
+    fn modulo_wraps_into_the_band() {
+        assert_eq!(first(Modulo::new(3.0, Arithmetic::new(-2.0, 1.0)), 7), [1.0, 2.0, 0.0, 1.0, 2.0, 0.0, 1.0]);
+    }
+
+    #[tokio::test]
+    async fn modulo_by_zero_is_rejected() {
+        let url = generator(config()).await;
+        let body = r#"{"range":{"from":0,"to":3,"step":1},"parameters":[0],"sequences":[{"name":"Arithmetic","parameters":[0,1],"sequences":[]}]}"#;
+        assert_eq!(post(&format!("{}/sequence/Modulo", url), body).await, (400, r#"{"code":400,"message":"Modulo by zero"}"#.to_string()));
+    }
+
+    #[test]
+    fn moving_average_cuts_the_window_off_at_index_0() {
+        let arithmetic = || Arithmetic::new(0.0, 1.0);
+        assert_eq!(first(MovingAverage::new(3, arithmetic()), 4), [0.5, 1.0, 2.0, 3.0]);
+        // Index 1 only has one element to its left: (0 + 1 + 2 + 3) / 4.
+        assert_eq!(first(MovingAverage::new(5, arithmetic()), 4), [1.0, 1.5, 2.0, 3.0]);
+        assert_eq!(first(MovingAverage::new(1, arithmetic()), 3), [0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn moving_average_of_3_is_smoothed() {
+        let geometric = || Geometric::new(1.0, 2.0);
+        assert_eq!(first(MovingAverage::new(3, geometric()), 6), first(Smoothed::new(geometric()), 6));
+    }
+
+    #[test]
+    fn smoothed_range_is_the_same_as_each_element() {
+        let smoothed = Smoothed::new(Geometric::new(1.0, 2.0));
+        let range = smoothed.range(Range { from: 2, to: 4, step: 1 });
+        assert_eq!(range, [smoothed.k_th(2), smoothed.k_th(3), smoothed.k_th(4)]);
+        // Index 2 reads index 1, which is outside the range: (2 + 4 + 8) / 3.
+        assert_eq!(range[0], 14.0 / 3.0);
+    }
+
+    #[test]
+    fn smoothed_averages_two_elements_at_index_0() {
+        assert_eq!(Smoothed::new(Geometric::new(1.0, 2.0)).k_th(0), 1.5);
+    }
+
+    #[test]
+    fn concat_splices_a_prefix_onto_a_tail() {
+        let concat = Concat::new(3, Constant::new(7.0), Arithmetic::new(0.0, 1.0));
+        assert_eq!(first(concat, 6), [7.0, 7.0, 7.0, 0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn repeat_tiles_its_block() {
+        assert_eq!(first(Repeat::new(vec![1.0, 2.0, 3.0]), 7), [1.0, 2.0, 3.0, 1.0, 2.0, 3.0, 1.0]);
+    }
+
+    #[test]
+    fn subsample_picks_every_s_th_element() {
+        assert_eq!(first(Subsample::new(2, Arithmetic::new(0.0, 1.0)), 4), [0.0, 2.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn subsample_past_the_last_index_is_nan() {
+        assert!(Subsample::new(2, Arithmetic::new(0.0, 1.0)).k_th(usize::MAX).is_nan());
+    }
+
+    #[test]
+    fn convolving_a_step_with_a_box_ramps_up() {
+        let step = Concat::new(2, Constant::new(0.0), Constant::new(1.0));
+        let convolve = Convolve::new(3, step, Constant::new(1.0));
+        assert_eq!(first(convolve, 7), [0.0, 0.0, 1.0, 2.0, 3.0, 3.0, 3.0]);
+    }
+
+    #[test]
+    fn convolve_clamps_negative_indices_to_0() {
+        let convolve = Convolve::new(3, Constant::new(1.0), Arithmetic::new(1.0, 1.0));
+        // 1 + 2 + 3, all reading index 0 of the first sequence.
+        assert_eq!(first(convolve, 1), [6.0]);
+    }
+
+    #[test]
+    fn recursive_honours_the_step() {
+        // x(k) = 2 x(k - 1) - x(k - 2) from 0 and 1 is the naturals, like Arithmetic(0, 1).
+        let recursive = Recursive::new(0.0, 1.0, 2.0, -1.0);
+        let arithmetic = Arithmetic::new(0.0, 1.0);
+        for (from, to, step) in [(0, 10, 2), (1, 9, 3)] {
+            assert_eq!(recursive.range(Range { from, to, step }), arithmetic.range(Range { from, to, step }));
+        }
+        assert_eq!(recursive.range(Range { from: 1, to: 9, step: 3 }), [1.0, 4.0, 7.0]);
+    }
+
+    #[test]
+    fn recursive_range_edges() {
+        let fibonacci = Recursive::new(0.0, 1.0, 1.0, 1.0);
+        let range = |from, to| fibonacci.range(Range { from, to, step: 1 });
+        assert_eq!(range(0, 0), [0.0]);
+        assert_eq!(range(1, 1), [1.0]);
+        assert_eq!(range(0, 1), [0.0, 1.0]);
+        assert_eq!(range(2, 5), [1.0, 2.0, 3.0, 5.0]);
+    }
+
+    #[tokio::test]
+    async fn schema_describes_sequence_requests() {
+        let url = generator(config()).await;
+        let (status, body) = get(&format!("{}/schema", url)).await;
+        assert_eq!(status, 200);
+        let schema: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(schema["title"], "SequenceRequest");
+        assert!(schema["properties"]["range"].is_object());
+        assert!(schema["$defs"]["Range"].is_object());
+        assert!(schema["$defs"]["SequenceSyntax"]["properties"]["sequences"].is_object());
+    }
+
+    #[test]
+    fn wrong_parameter_count_names_the_nested_node() {
+        let drop = node("Drop", &[], vec![node("Arithmetic", &[0.0, 1.0], vec![])]);
+        let sum = node("Sum", &[], vec![node("Constant", &[1.0], vec![]), drop]);
+        let error = validate_syntax(&sum.name, &sum.parameters, &sum.sequences, &sum.name).unwrap_err();
+        assert_eq!(error.to_string(), "Sum.sequences[1]: Drop expects 1 parameters, got 0");
+    }
+
+    #[tokio::test]
+    async fn wrong_parameter_count_is_a_400_response() {
+        let url = generator(config()).await;
+        let abs = node("Abs", &[], vec![node("Arithmetic", &[1.0], vec![])]);
+        let (status, body) = request(&url, &node("Alternating", &[], vec![abs]), 0, 2).await;
+        assert_eq!(status, 400);
+        assert_eq!(body, r#"{"code":400,"message":"Alternating.sequences[0].sequences[0]: Arithmetic expects 2 parameters, got 1"}"#);
+    }
+
+    #[tokio::test]
+    async fn repeat_needs_a_block() {
+        let url = generator(config()).await;
+        let (status, body) = request(&url, &node("Repeat", &[], vec![]), 0, 2).await;
+        assert_eq!(status, 400);
+        assert_eq!(body, r#"{"code":400,"message":"Repeat: Repeat expects at least 1 parameters, got 0"}"#);
+    }
+
+    #[tokio::test]
+    async fn paths_that_name_no_sequence_are_404_without_asking_the_registry() {
+        let asked = Arc::new(AtomicU64::new(0));
+        let counter = asked.clone();
+        let registry = stub(move |_, _, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            (StatusCode::OK, "[]".to_string())
+        })
+        .await;
+        let url = generator(delegating(registry)).await;
+        for path in ["/foo", "/sequence", "/sequence/Sum/extra"] {
+            assert_eq!(post(&format!("{}{}", url, path), FOO).await.0, 404, "{}", path);
+        }
+        assert_eq!(asked.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn batch_answers_in_request_order() {
+        let url = generator(config()).await;
+        let range = serde_json::json!({ "from": 0, "to": 2, "step": 1 });
+        let batch = serde_json::json!([
+            { "name": "Geometric", "request": { "range": range, "parameters": [1.0, 2.0], "sequences": [] } },
+            { "name": "Sum", "request": { "range": range, "parameters": [], "sequences": [
+                { "name": "Constant", "parameters": [1.0], "sequences": [] },
+                { "name": "Arithmetic", "parameters": [0.0, 1.0], "sequences": [] },
+            ] } },
+            { "name": "Repeat", "request": { "range": range, "parameters": [1.0, 1.0, 2.0], "sequences": [] } },
+        ]);
+        let (status, body) = post(&format!("{}/sequence/batch", url), &batch.to_string()).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, "[[1.0,2.0,4.0],[1.0,2.0,3.0],[1.0,1.0,2.0]]");
+    }
+
+    #[tokio::test]
+    async fn batch_reports_each_failure_in_its_place() {
+        let url = generator(config()).await;
+        let batch = r#"[
+            {"name": "Constant", "request": {"range": {"from": 0, "to": 0, "step": 1}, "parameters": [3], "sequences": []}},
+            {"name": "Constant", "request": {"range": {"from": 0, "to": 0, "step": 1}, "parameters": [], "sequences": []}}
+        ]"#;
+        let results: serde_json::Value = serde_json::from_str(&post(&format!("{}/sequence/batch", url), batch).await.1).unwrap();
+        assert_eq!(results[0], serde_json::json!([3.0]));
+        assert!(results[1]["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn batch_has_at_most_max_batch_items() {
+        let url = generator(Config { max_batch_items: 2, ..config() }).await;
+        let item = r#"{"name": "Constant", "request": {"range": {"from": 0, "to": 0, "step": 1}, "parameters": [3], "sequences": []}}"#;
+        let batch = |n| format!("[{}]", vec![item; n].join(","));
+        assert_eq!(post(&format!("{}/sequence/batch", url), &batch(2)).await, (200, "[[3.0],[3.0]]".to_string()));
+        let (status, body) = post(&format!("{}/sequence/batch", url), &batch(3)).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Batch has 3 items, at most 2 allowed"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn random_is_the_same_for_the_same_request() {
+        let url = generator(config()).await;
+        let random = node("Random", &[42.0, -1.0, 1.0], vec![]);
+        let (status, body) = request(&url, &random, 0, 99).await;
+        assert_eq!(status, 200);
+        assert_eq!(request(&url, &random, 0, 99).await.1, body);
+        let values: Vec<f64> = serde_json::from_str(&body).unwrap();
+        assert!(values.iter().all(|x| (-1.0..1.0).contains(x)));
+        // Not just the same value over and over.
+        assert!(values.iter().any(|&x| x != values[0]));
+    }
+
+    #[test]
+    fn random_depends_on_the_seed_not_on_the_range() {
+        let random = Random::new(42, 0.0, 1.0);
+        let other = Random::new(43, 0.0, 1.0);
+        assert_eq!(random.range(Range { from: 5, to: 5, step: 1 }), random.range(Range { from: 0, to: 5, step: 1 })[5..]);
+        assert_ne!(random.k_th(5), other.k_th(5));
+    }
+
+    #[tokio::test]
+    async fn body_over_the_declared_limit_is_413() {
+        let url = generator(Config { body_limit: 64, ..config() }).await;
+        let (status, _) = post(&format!("{}/sequence/Arithmetic", url), &" ".repeat(65)).await;
+        assert_eq!(status, 413);
+        // Exactly at the limit is still read, and then rejected for not being a request.
+        let (status, _) = post(&format!("{}/sequence/Arithmetic", url), &" ".repeat(64)).await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn streamed_body_over_the_limit_is_413() {
+        let url = generator(Config { body_limit: 64, ..config() }).await;
+        let chunk = format!("28\r\n{}\r\n", " ".repeat(40));
+        let request = format!(
+            "POST /sequence/Arithmetic HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{}{}0\r\n\r\n",
+            chunk, chunk,
+        );
+        let response = raw(&url, &request).await;
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+    }
+
+    #[test]
+    fn sine_and_cosine_at_quarter_periods() {
+        let quarter = std::f64::consts::FRAC_PI_2;
+        assert_close(&first(Sine::new(2.0, quarter, 0.0), 5), &[0.0, 2.0, 0.0, -2.0, 0.0]);
+        assert_close(&first(Cosine::new(2.0, quarter, 0.0), 5), &[2.0, 0.0, -2.0, 0.0, 2.0]);
+    }
+
+    #[test]
+    fn phase_shifts_sine_onto_cosine() {
+        let quarter = std::f64::consts::FRAC_PI_2;
+        assert_close(&first(Sine::new(1.0, 0.3, quarter), 10), &first(Cosine::new(1.0, 0.3, 0.0), 10));
+        // A phase of pi flips the sine, so the first peak is -amplitude.
+        assert_close(&first(Sine::new(3.0, quarter, 2.0 * quarter), 2), &[0.0, -3.0]);
+    }
+
+    #[test]
+    fn ema_converges_towards_a_constant_input() {
+        // 0 once, then 10 from index 1 on.
+        let input = || Concat::new(1, Constant::new(0.0), Constant::new(10.0));
+        assert_eq!(first(Ema::new(0.5, input()), 4), [0.0, 5.0, 7.5, 8.75]);
+        let ema = Ema::new(0.5, input());
+        assert!((10.0 - ema.k_th(50)).abs() < 1e-9);
+        assert_eq!(ema.range(Range { from: 2, to: 3, step: 1 }), [7.5, 8.75]);
+    }
+
+    #[tokio::test]
+    async fn ema_alpha_must_be_between_0_and_1() {
+        let url = generator(config()).await;
+        for alpha in ["-0.1", "1.1"] {
+            let body = format!(r#"{{"range":{{"from":0,"to":3,"step":1}},"parameters":[{}],"sequences":[{{"name":"Constant","parameters":[1],"sequences":[]}}]}}"#, alpha);
+            assert_eq!(post(&format!("{}/sequence/Ema", url), &body).await, (400, r#"{"code":400,"message":"Ema alpha must be between 0 and 1"}"#.to_string()));
+        }
+        // Both ends are allowed: 1 is the input itself, 0 its first element forever.
+        assert_eq!(first(Ema::new(1.0, Arithmetic::new(0.0, 1.0)), 3), [0.0, 1.0, 2.0]);
+        assert_eq!(first(Ema::new(0.0, Arithmetic::new(4.0, 1.0)), 3), [4.0, 4.0, 4.0]);
+    }
+
+    #[test]
+    fn running_extremes_of_an_oscillating_input() {
+        // 0, -1, 2, -3, 4, -5
+        let oscillating = || Alternating::new(Arithmetic::new(0.0, 1.0));
+        assert_eq!(first(RunningMax::new(oscillating()), 6), [0.0, 0.0, 2.0, 2.0, 4.0, 4.0]);
+        assert_eq!(first(RunningMin::new(oscillating()), 6), [0.0, -1.0, -1.0, -3.0, -3.0, -5.0]);
+        // A range not starting at 0 still looks at everything before it.
+        let max = RunningMax::new(oscillating());
+        assert_eq!(max.range(Range { from: 3, to: 5, step: 2 }), [2.0, 4.0]);
+    }
+
+    #[tokio::test]
+    async fn second_page_of_a_100_element_range() {
+        let url = generator(config()).await;
+        let body = r#"{"range": {"from": 0, "to": 198, "step": 2}, "parameters": [0, 1], "sequences": []}"#;
+        let (status, body) = post(&format!("{}/sequence/Arithmetic?offset=10&limit=10", url), body).await;
+        assert_eq!(status, 200);
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(page["total"], 100);
+        assert_eq!(page["offset"], 10);
+        assert_eq!(page["limit"], 10);
+        let expected: Vec<f64> = (10..20).map(|k| 2.0 * k as f64).collect();
+        assert_eq!(page["values"], serde_json::json!(expected));
+    }
+
+    #[tokio::test]
+    async fn page_past_the_end_is_empty() {
+        let url = generator(config()).await;
+        let (_, body) = post(&format!("{}/sequence/Constant?offset=200", url), r#"{"range": {"from": 0, "to": 99, "step": 1}, "parameters": [1], "sequences": []}"#).await;
+        assert_eq!(body, r#"{"total":100,"offset":100,"limit":0,"values":[]}"#);
+    }
+
+    #[tokio::test]
+    async fn matching_etag_is_304() {
+        let url = format!("{}/sequence/Arithmetic", generator(config()).await);
+        let client = reqwest::Client::new();
+        let body = r#"{"range": {"from": 0, "to": 2, "step": 1}, "parameters": [1, 1], "sequences": []}"#;
+        let response = client.post(&url).body(body).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        let etag = response.headers()[ETAG].clone();
+
+        let response = client.post(&url).header(IF_NONE_MATCH, etag.clone()).body(body).send().await.unwrap();
+        assert_eq!(response.status(), 304);
+        assert_eq!(response.headers()[ETAG], etag);
+        assert_eq!(response.headers()[VARY], "Accept-Encoding");
+        assert!(response.headers().contains_key(X_REQUEST_ID));
+        assert_eq!(response.text().await.unwrap(), "");
+
+        // Another request is another result.
+        let other = body.replace("[1, 1]", "[1, 2]");
+        let response = client.post(&url).header(IF_NONE_MATCH, etag.clone()).body(other).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_ne!(response.headers()[ETAG], etag);
+    }
+
+    #[tokio::test]
+    async fn etag_is_only_confirmed_for_requests_that_evaluate() {
+        let url = format!("{}/sequence/Arithmetic", generator(config()).await);
+        let client = reqwest::Client::new();
+        let body = r#"{"range": {"from": 0, "to": 2, "step": 1}, "parameters": [1, 1], "sequences": []}"#;
+        // * needs a current representation, which only evaluating shows.
+        let response = client.post(&url).header(IF_NONE_MATCH, "*").body(body).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        // Tags of requests that fail, as a client might have guessed them.
+        for (body, status) in [
+            ("{", 400),
+            (r#"{"range": {"from": 0, "to": 2, "step": 1}, "parameters": [1], "sequences": []}"#, 400),
+        ] {
+            let tag = etag("Arithmetic", body, None, None, None, false);
+            let response = client.post(&url).header(IF_NONE_MATCH, tag).body(body).send().await.unwrap();
+            assert_eq!(response.status(), status, "{}", body);
+        }
+    }
+
+    #[test]
+    fn ema_of_nothing_is_nothing() {
+        // TakeUntil ends at once, as 0 is already above -1.
+        let empty = TakeUntil::new(-1.0, Constant::new(0.0));
+        assert!(Ema::new(0.5, empty).range(Range { from: 0, to: 3, step: 1 }).is_empty());
+    }
+
+    #[test]
+    fn take_until_stops_past_the_threshold() {
+        let take_until = TakeUntil::new(10.0, Arithmetic::new(0.0, 3.0));
+        assert_eq!(take_until.range(Range { from: 0, to: 20, step: 1 }), [0.0, 3.0, 6.0, 9.0]);
+        assert_eq!(take_until.range(Range { from: 2, to: 20, step: 1 }), [6.0, 9.0]);
+        // The threshold itself is still in.
+        assert_eq!(first(TakeUntil::new(10.0, Arithmetic::new(0.0, 1.0)), 21).len(), 11);
+    }
+
+    // Value of every sample in the exposition, keyed by name and labels.
+    fn samples(metrics: &str) -> BTreeMap<String, f64> {
+        metrics
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let (name, value) = line.rsplit_once(' ').expect("sample without a value");
+                (name.to_string(), value.parse().expect("value is not a number"))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn metrics_count_requests() {
+        let url = generator(config()).await;
+        let before = samples(&get(&format!("{}/metrics", url)).await.1);
+        request(&url, &node("Arithmetic", &[0.0, 1.0], vec![]), 0, 3).await;
+        // Rejected for its Arithmetic before anyone is asked for it.
+        request(&url, &node("NoSuchSequence", &[], vec![node("Arithmetic", &[], vec![])]), 0, 3).await;
+        let (status, metrics) = get(&format!("{}/metrics", url)).await;
+        assert_eq!(status, 200);
+        let after = samples(&metrics);
+        let increase = |name: &str| after[name] - before.get(name).copied().unwrap_or(0.0);
+        // Other tests run at the same time, so these may have grown by more.
+        assert!(increase("generator_requests_total") >= 2.0);
+        assert!(increase(r#"generator_sequence_requests_total{sequence="Arithmetic"}"#) >= 1.0);
+        assert!(increase(r#"generator_sequence_requests_total{sequence="other"}"#) >= 1.0);
+        assert!(!metrics.contains("NoSuchSequence"));
+        assert!(after["generator_response_size_bytes_count"] >= 1.0);
+    }
+
+    #[tokio::test]
+    async fn serves_http2_with_prior_knowledge() {
+        let url = generator(Config { http2: true, ..config() }).await;
+        let client = reqwest::Client::builder().http2_prior_knowledge().build().unwrap();
+        let body = r#"{"range": {"from": 0, "to": 2, "step": 1}, "parameters": [1, 1], "sequences": []}"#;
+        let requests = (0..3).map(|_| client.post(format!("{}/sequence/Arithmetic", url)).body(body).send());
+        for response in requests.collect::<Vec<_>>() {
+            let response = response.await.unwrap();
+            assert_eq!(response.version(), reqwest::Version::HTTP_2);
+            assert_eq!(response.text().await.unwrap(), "[1.0,2.0,3.0]");
+        }
+        // HTTP/1.1 clients still work.
+        let response = reqwest::get(format!("{}/ping", url)).await.unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_11);
+    }
+
+    #[tokio::test]
+    async fn http2_is_off_by_default() {
+        let url = generator(config()).await;
+        let client = reqwest::Client::builder().http2_prior_knowledge().build().unwrap();
+        assert!(client.get(format!("{}/ping", url)).send().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn evaluations_beyond_the_limit_are_503() {
+        let state: Shared = Arc::new(State::new(Config { max_evaluations: 1, ..config() }));
+        let (release, wait) = std::sync::mpsc::channel::<()>();
+        let busy = {
+            let state = state.clone();
+            tokio::spawn(async move { run_blocking(&state, move || wait.recv().unwrap()).await })
+        };
+        while state.evaluations.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+        let error = run_blocking(&state, || ()).await.unwrap_err();
+        assert_eq!(error.status, StatusCode::SERVICE_UNAVAILABLE);
+
+        release.send(()).unwrap();
+        busy.await.unwrap().unwrap();
+        assert!(run_blocking(&state, || ()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn validate_accepts_a_valid_nested_request_without_computing_it() {
+        let url = generator(config()).await;
+        // A million elements of a kernel this long would take far too long to compute.
+        let kernel = node("Smoothed", &[], vec![node("Arithmetic", &[0.0, 1.0], vec![])]);
+        let convolve = node("Convolve", &[1e15], vec![node("Constant", &[1.0], vec![]), kernel]);
+        let (status, body) = post(&format!("{}/sequence/Convolve/validate", url), &request_body(&convolve, 0, 999_999)).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"valid":true}"#);
+    }
+
+    #[tokio::test]
+    async fn validate_explains_an_invalid_nested_request() {
+        let url = generator(config()).await;
+        let sum = node("Sum", &[], vec![node("Constant", &[1.0], vec![]), node("Modulo", &[0.0], vec![node("Constant", &[1.0], vec![])])]);
+        let (status, body) = post(&format!("{}/sequence/Sum/validate", url), &request_body(&sum, 0, 9)).await;
+        assert_eq!(status, 200);
+        let validation: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(validation["valid"], false);
+        assert_eq!(validation["error"], "Modulo by zero");
+
+        let sum = node("Sum", &[], vec![node("Constant", &[1.0], vec![])]);
+        let (_, body) = post(&format!("{}/sequence/Sum/validate", url), &request_body(&sum, 0, 9)).await;
+        assert_eq!(body, r#"{"valid":false,"error":"Sum: Sum expects 2 sequences, got 1"}"#);
+    }
+
+    #[tokio::test]
+    async fn validate_takes_off_one_suffix() {
+        let url = generator(config()).await;
+        let body = request_body(&node("Arithmetic", &[0.0, 1.0], vec![]), 0, 9);
+        let (status, body) = post(&format!("{}/sequence/Arithmetic/validate/validate", url), &body).await;
+        assert_eq!(status, 404);
+        assert!(body.contains("No sequence named Arithmetic/validate"), "{}", body);
+    }
+
+    #[test]
+    fn this_node_is_recognised_in_the_registry() {
+        assert!(is_self(&serde_json::json!({ "ip": "127.0.0.1", "port": PORT })));
+        assert!(is_self(&serde_json::json!({ "ip": "127.0.0.1", "port": PORT.to_string() })));
+        assert!(!is_self(&serde_json::json!({ "ip": "127.0.0.1", "port": PORT + 1 })));
+        assert!(!is_self(&serde_json::json!({ "ip": "10.0.0.1", "port": PORT })));
+        let projects = serde_json::json!([{ "ip": "127.0.0.1", "port": PORT }, { "ip": "127.0.0.1", "port": 1 }]);
+        assert_eq!(count_peers(&projects), 1);
+    }
+
+    #[test]
+    fn threshold_of_a_sine() {
+        let sine = || Sine::new(1.0, std::f64::consts::FRAC_PI_2, 0.0);
+        assert_eq!(first(Threshold::new(0.5, sine()), 8), [0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        // Reaching the threshold is enough.
+        assert_eq!(first(Threshold::new(-1.0, sine()), 4), [1.0; 4]);
+    }
+
+    #[tokio::test]
+    async fn cors_preflight() {
+        let url = generator(Config { cors_origin: "http://localhost:5173".to_string(), ..config() }).await;
+        let response = reqwest::Client::new()
+            .request(reqwest::Method::OPTIONS, format!("{}/sequence/Arithmetic", url))
+            .header("Origin", "http://localhost:5173")
+            .header("Access-Control-Request-Method", "POST")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 204);
+        let headers = response.headers();
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "http://localhost:5173");
+        assert!(headers[ACCESS_CONTROL_ALLOW_METHODS].to_str().unwrap().contains("POST"));
+        assert!(headers[ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap().contains("Content-Type"));
+
+        // Other responses carry the origin too.
+        let response = reqwest::get(format!("{}/ping", url)).await.unwrap();
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "http://localhost:5173");
+    }
+
+    #[test]
+    fn first_16_bit_counts_and_powers_of_two() {
+        assert_eq!(
+            first(BitCount::new(), 16),
+            [0.0, 1.0, 1.0, 2.0, 1.0, 2.0, 2.0, 3.0, 1.0, 2.0, 2.0, 3.0, 2.0, 3.0, 3.0, 4.0],
+        );
+        let powers: Vec<f64> = (0..16).map(|k| (1u32 << k) as f64).collect();
+        assert_eq!(first(PowerOfTwo::new(), 16), powers);
+    }
+
+    #[test]
+    fn gaussian_is_reproducible_around_its_mean() {
+        let range = || Range { from: 0, to: 19_999, step: 1 };
+        let values = Gaussian::new(7, 3.0, 2.0).range(range());
+        assert_eq!(Gaussian::new(7, 3.0, 2.0).range(range()), values);
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / values.len() as f64;
+        // The mean of 20000 samples is typically off by 2 / sqrt(20000), about 0.014.
+        assert!((mean - 3.0).abs() < 0.1, "mean {}", mean);
+        assert!((variance.sqrt() - 2.0).abs() < 0.1, "stddev {}", variance.sqrt());
+        assert_ne!(Gaussian::new(8, 3.0, 2.0).k_th(0), values[0]);
+    }
+
+    #[test]
+    fn logistic_at_r_2_converges_to_one_half() {
+        let logistic = Logistic::new(2.0, 0.1);
+        let values = logistic.range(Range { from: 0, to: 20, step: 1 });
+        assert_close(&values[..3], &[0.1, 0.18, 0.2952]);
+        assert!((values[20] - 0.5).abs() < 1e-9);
+        assert_eq!(logistic.range(Range { from: 18, to: 20, step: 2 }), [values[18], values[20]]);
+    }
+
+    #[tokio::test]
+    async fn logistic_x0_must_be_between_0_and_1() {
+        let url = generator(config()).await;
+        for x0 in [-0.1, 1.1] {
+            let (status, body) = request(&url, &node("Logistic", &[2.0, x0], vec![]), 0, 3).await;
+            assert_eq!((status, body.as_str()), (400, r#"{"code":400,"message":"Logistic x0 must be between 0 and 1"}"#));
+        }
+    }
+
+    #[test]
+    fn compose_samples_at_the_computed_indices() {
+        let squares = Prod::new(Arithmetic::new(0.0, 1.0), Arithmetic::new(0.0, 1.0));
+        let evens = Arithmetic::new(0.0, 2.0);
+        assert_eq!(first(Compose::new(squares, evens), 4), [0.0, 4.0, 16.0, 36.0]);
+    }
+
+    #[test]
+    fn compose_gives_0_at_negative_or_nan_indices() {
+        let naturals = || Arithmetic::new(0.0, 1.0);
+        assert_eq!(first(Compose::new(naturals(), Arithmetic::new(-1.0, -1.0)), 2), [0.0, 0.0]);
+        let compose = Compose::new(Arithmetic::new(5.0, 1.0), Constant::new(f64::NAN));
+        assert_eq!(compose.k_th(0), 0.0);
+    }
+
+    // Request for a sequence by a name in the path, with nothing else to it.
+    const FOO: &str = r#"{"range":{"from":0,"to":0,"step":1},"parameters":[],"sequences":[]}"#;
+
+    #[tokio::test]
+    async fn errors_are_json() {
+        let url = generator(Config { body_limit: 128, ..config() }).await;
+        let client = reqwest::Client::new();
+        let errors = [
+            (client.get(format!("{}/nothing/here", url)), 404),
+            (client.post(format!("{}/sequence/Nope", url)).body(FOO), 404),
+            (client.post(format!("{}/sequence/Arithmetic", url)).body("{"), 400),
+            (client.post(format!("{}/sequence/Arithmetic", url)).body(" ".repeat(129)), 413),
+        ];
+        for (request, code) in errors {
+            let response = request.send().await.unwrap();
+            assert_eq!(response.status().as_u16(), code);
+            assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+            let error: ErrorResponse = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+            assert_eq!(error.code, code);
+            assert!(!error.message.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn evaluate_takes_the_whole_tree_from_the_body() {
+        let url = generator(config()).await;
+        let syntax = node("Sum", &[], vec![
+            node("Constant", &[10.0], vec![]),
+            node("Drop", &[2.0], vec![node("Arithmetic", &[0.0, 1.0], vec![])]),
+        ]);
+        let body = serde_json::json!({ "range": { "from": 0, "to": 3, "step": 1 }, "syntax": syntax });
+        assert_eq!(post(&format!("{}/evaluate", url), &body.to_string()).await, (200, "[12.0,13.0,14.0,15.0]".to_string()));
+    }
+
+    #[tokio::test]
+    async fn evaluate_rejects_a_bad_tree() {
+        let url = generator(config()).await;
+        let body = serde_json::json!({ "range": { "from": 0, "to": 3, "step": 1 }, "syntax": node("Abs", &[], vec![]) });
+        assert_eq!(post(&format!("{}/evaluate", url), &body.to_string()).await.0, 400);
+    }
+
+    #[tokio::test]
+    async fn range_only_sequences_need_a_parent_that_reads_a_range() {
+        let url = generator(config()).await;
+        let reverse = || node("Reverse", &[], vec![node("Arithmetic", &[0.0, 1.0], vec![])]);
+        let sum = node("Sum", &[], vec![reverse(), node("Constant", &[1.0], vec![])]);
+        let (status, body) = request(&url, &sum, 0, 2).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Sum.sequences[0]: Reverse is only defined over a range and can't be used inside Sum"), "{}", body);
+        // RunningMax reads its sequence as a range, so Reverse is fine there.
+        let max = node("RunningMax", &[], vec![reverse()]);
+        assert_eq!(request(&url, &max, 0, 2).await, (200, "[2.0,2.0,2.0]".to_string()));
+    }
+
+    #[test]
+    fn p_series_at_p_2() {
+        assert_close(&first(PSeries::new(2.0), 4), &[1.0, 0.25, 1.0 / 9.0, 0.0625]);
+        // The base is k + 1, so p = -1 gives the naturals from 1.
+        assert_eq!(first(PSeries::new(-1.0), 3), [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn piecewise_switches_without_reindexing() {
+        let piecewise = Piecewise::new(5, Constant::new(0.0), Arithmetic::new(0.0, 1.0));
+        assert_eq!(first(piecewise, 8), [0.0, 0.0, 0.0, 0.0, 0.0, 5.0, 6.0, 7.0]);
+    }
+
+    #[tokio::test]
+    async fn range_over_the_cap_is_400() {
+        let url = generator(Config { max_range_len: 100, ..config() }).await;
+        let arithmetic = node("Arithmetic", &[0.0, 1.0], vec![]);
+        assert_eq!(request(&url, &arithmetic, 0, 99).await.0, 200);
+        let (status, body) = request(&url, &arithmetic, 0, 100).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Range has 101 elements, at most 100 allowed"), "{}", body);
+        // Only the emitted elements count, not the span.
+        let sparse = r#"{"range": {"from": 0, "to": 1000000000, "step": 100000000}, "parameters": [0, 1], "sequences": []}"#;
+        assert_eq!(post(&format!("{}/sequence/Arithmetic", url), sparse).await.0, 200);
+    }
+
+    #[tokio::test]
+    async fn sequences_computed_from_index_0_must_end_below_the_cap() {
+        let url = generator(Config { max_range_len: 100, ..config() }).await;
+        let running_max = node("RunningMax", &[], vec![node("Arithmetic", &[0.0, 1.0], vec![])]);
+        assert_eq!(request(&url, &running_max, 99, 99).await.0, 200);
+        let (status, body) = request(&url, &running_max, 100, 100).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("RunningMax computes every element from index 0"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn version_is_the_package_version() {
+        let url = generator(config()).await;
+        let (status, body) = get(&format!("{}/version", url)).await;
+        assert_eq!(status, 200);
+        let version: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(version["target"], env!("BUILD_TARGET"));
+        assert_eq!(version["commit"].as_str(), option_env!("GIT_COMMIT"));
+    }
+
+    #[tokio::test]
+    async fn no_registry_calls_without_delegation() {
+        let asked = Arc::new(AtomicU64::new(0));
+        let counter = asked.clone();
+        let registry = stub(move |_, _, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            (StatusCode::OK, "[]".to_string())
+        })
+        .await;
+        let config = Config { registry_url: format!("http://{}", registry), heartbeat_interval: Duration::from_millis(10), ..config() };
+        let state: Shared = Arc::new(State::new(config.clone()));
+        join_registry(&state).await;
+
+        let url = generator(config).await;
+        let (status, body) = post(&format!("{}/sequence/Foo", url), FOO).await;
+        assert_eq!(status, 404);
+        assert!(body.contains("delegation is disabled"), "{}", body);
+        assert_eq!(get(&format!("{}/peers", url)).await, (200, "{}".to_string()));
+        assert_eq!(get(&format!("{}/ready", url)).await.0, 200);
+        // Long enough for a few heartbeats, had one been started.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(asked.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn joins_the_registry_with_delegation() {
+        let (registry, projects) = registry(Vec::new()).await;
+        let state: Shared = Arc::new(State::new(delegating(registry)));
+        join_registry(&state).await;
+        assert_eq!(projects.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn floor_ceil_and_round_of_halves() {
+        let halves = || Arithmetic::new(0.5, 1.0);
+        assert_eq!(first(Floor::new(halves()), 3), [0.0, 1.0, 2.0]);
+        assert_eq!(first(Ceil::new(halves()), 3), [1.0, 2.0, 3.0]);
+        // Halves round away from zero.
+        assert_eq!(first(Round::new(halves()), 3), [1.0, 2.0, 3.0]);
+        assert_eq!(first(Round::new(Arithmetic::new(-0.5, -1.0)), 2), [-1.0, -2.0]);
+    }
+
+    #[test]
+    fn clamp_between_two_envelopes() {
+        let clamp = ClampBetween::new(Arithmetic::new(0.0, 1.0), Constant::new(2.0), Constant::new(4.0));
+        assert_eq!(first(clamp, 7), [2.0, 2.0, 2.0, 3.0, 4.0, 4.0, 4.0]);
+    }
+
+    #[test]
+    fn clamp_between_follows_moving_bounds() {
+        let clamp = ClampBetween::new(Constant::new(0.0), Arithmetic::new(-1.0, 1.0), Arithmetic::new(1.0, 1.0));
+        assert_eq!(first(clamp, 4), [0.0, 0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn every_registered_sequence_builds_and_evaluates() {
+        for entry in REGISTRY {
+            let parameters = vec![1.0; entry.parameters.len()];
+            let children: Vec<_> = (0..entry.sequences).map(|_| Box::new(node("Constant", &[1.0], vec![]))).collect();
+            let seq = build_sequence(entry.name, &parameters, &children).unwrap();
+            assert_eq!(first(seq, 5).len(), 5, "{}", entry.name);
+
+            if entry.sequences > 0 {
+                assert!(validate_syntax(entry.name, &parameters, &[], entry.name).is_err(), "{}", entry.name);
+            }
+        }
+    }
+
+    #[test]
+    fn the_original_sequences_still_work() {
+        let arithmetic = || Box::new(node("Arithmetic", &[1.0, 2.0], vec![]));
+        let geometric = || Box::new(node("Geometric", &[1.0, 3.0], vec![]));
+        let built = |name, parameters: &[f64], sequences: &[Box<SequenceSyntax>], n| first(build_sequence(name, parameters, sequences).unwrap(), n);
+        assert_eq!(built("Arithmetic", &[1.0, 2.0], &[], 4), [1.0, 3.0, 5.0, 7.0]);
+        assert_eq!(built("Geometric", &[1.0, 3.0], &[], 4), [1.0, 3.0, 9.0, 27.0]);
+        assert_eq!(built("Constant", &[4.0], &[], 2), [4.0, 4.0]);
+        assert_eq!(built("Sum", &[], &[arithmetic(), geometric()], 3), [2.0, 6.0, 14.0]);
+        assert_eq!(built("Prod", &[], &[arithmetic(), geometric()], 3), [1.0, 9.0, 45.0]);
+        assert_eq!(built("Drop", &[2.0], &[geometric()], 2), [9.0, 27.0]);
+    }
+
+    #[test]
+    fn listing_matches_the_registry() {
+        let listed = sequences();
+        assert_eq!(listed.len(), REGISTRY.len());
+        for (info, entry) in listed.iter().zip(REGISTRY) {
+            assert_eq!(info.name, entry.name);
+            assert_eq!(info.parameters as usize, entry.parameters.len());
+            assert_eq!(info.sequences, entry.sequences);
+        }
+    }
+
+    #[tokio::test]
+    async fn non_finite_parameters_are_400() {
+        let sum = node("Sum", &[], vec![node("Constant", &[1.0], vec![]), node("Constant", &[f64::INFINITY], vec![])]);
+        let request = SequenceRequest {
+            range: Range { from: 0, to: 2, step: 1 },
+            parameters: sum.parameters,
+            sequences: sum.sequences,
+            request_id: None,
+            peers: None,
+            signed_range: None,
+            indices: None,
+        };
+        let listed = sequences();
+        let info = listed.iter().find(|info| info.name == "Sum").unwrap();
+        let error = build(&request, info, &config()).err().unwrap();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.message, "Sum.sequences[1]: parameter 0 is not a finite number");
+
+        // JSON has no infinity, and numbers too large for an f64 don't parse.
+        let url = generator(config()).await;
+        let body = r#"{"range": {"from": 0, "to": 2, "step": 1}, "parameters": [1e999], "sequences": []}"#;
+        assert_eq!(post(&format!("{}/sequence/Constant", url), body).await.0, 400);
+    }
+
+    #[test]
+    fn smoothed_literal() {
+        assert_eq!(first(Literal::new(vec![3.0, 6.0, 9.0]), 5), [3.0, 6.0, 9.0, 0.0, 0.0]);
+        // Past its values the literal is 0, so the last one is averaged with a 0.
+        assert_eq!(first(Smoothed::new(Literal::new(vec![3.0, 6.0, 9.0])), 5), [4.5, 6.0, 5.0, 3.0, 0.0]);
+    }
+
+    #[test]
+    fn literal_needs_a_value() {
+        let error = validate_syntax("Literal", &[], &[], "Literal").unwrap_err();
+        assert_eq!(error.to_string(), "Literal: Literal expects at least 1 parameters, got 0");
+    }
+
+    #[tokio::test]
+    async fn request_id_round_trips() {
+        let url = format!("{}/sequence/Constant", generator(config()).await);
+        let client = reqwest::Client::new();
+        let body = r#"{"range": {"from": 0, "to": 0, "step": 1}, "parameters": [1], "sequences": [], "request_id": "abc-123"}"#;
+        let response = client.post(&url).body(body).send().await.unwrap();
+        assert_eq!(response.headers()[X_REQUEST_ID], "abc-123");
+        // Errors carry it too.
+        let response = client.post(&url).body(body.replace("[1]", "[]")).send().await.unwrap();
+        assert_eq!(response.status(), 400);
+        assert_eq!(response.headers()[X_REQUEST_ID], "abc-123");
+
+        let response = client.post(&url).body(body.replace(r#", "request_id": "abc-123""#, "")).send().await.unwrap();
+        let generated = response.headers()[X_REQUEST_ID].to_str().unwrap();
+        assert!(generated.len() == 16 && generated.chars().all(|c| c.is_ascii_hexdigit()), "{}", generated);
+    }
+
+    #[tokio::test]
+    async fn ping_registry_reports_reachability_and_latency() {
+        let registry = stub_with_delay(Duration::from_millis(50), |_, _, _| (StatusCode::OK, "[]".to_string())).await;
+        let url = generator(delegating(registry)).await;
+        let (status, body) = get(&format!("{}/ping/registry", url)).await;
+        assert_eq!(status, 200);
+        let ping: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(ping["reachable"], true);
+        assert!(ping["latency_ms"].as_u64().unwrap() >= 50);
+
+        let url = generator(delegating(([127, 0, 0, 1], 1).into())).await;
+        assert_eq!(get(&format!("{}/ping/registry", url)).await.1, r#"{"reachable":false,"latency_ms":null}"#);
+    }
+
+    #[test]
+    fn first_ten_catalan_numbers() {
+        assert_eq!(first(Catalan::new(), 10), [1.0, 1.0, 2.0, 5.0, 14.0, 42.0, 132.0, 429.0, 1430.0, 4862.0]);
+        let catalan = Catalan::new();
+        assert_eq!(catalan.range(Range { from: 5, to: 9, step: 2 }), [42.0, 429.0, 4862.0]);
+        assert_eq!(catalan.k_th(9), 4862.0);
+    }
+
+    #[test]
+    fn binomial_row_4() {
+        assert_eq!(first(Binomial::new(4), 7), [1.0, 4.0, 6.0, 4.0, 1.0, 0.0, 0.0]);
+        // Large rows don't overflow on the way, as factorials would.
+        let binomial = Binomial::new(200);
+        assert_eq!(binomial.k_th(1), 200.0);
+        assert_eq!(binomial.k_th(200), 1.0);
+        assert!(binomial.k_th(100).is_finite());
+    }
+
+    #[test]
+    fn negate_geometric() {
+        assert_eq!(first(Negate::new(Geometric::new(1.0, 2.0)), 4), [-1.0, -2.0, -4.0, -8.0]);
+    }
+
+    #[test]
+    fn growth_ratio_of_geometric_is_its_factor() {
+        assert_eq!(first(GrowthRatio::new(Geometric::new(5.0, 3.0)), 5), [3.0; 5]);
+    }
+
+    #[tokio::test]
+    async fn growth_ratio_after_a_zero_is_null() {
+        let url = generator(config()).await;
+        // 0, 1, 2: the ratio at 0 divides by zero.
+        let ratio = node("GrowthRatio", &[], vec![node("Arithmetic", &[0.0, 1.0], vec![])]);
+        assert_eq!(request(&url, &ratio, 0, 1).await, (200, "[null,2.0]".to_string()));
+    }
+
+    fn pinned(peers: &[SocketAddr]) -> String {
+        let peers: Vec<serde_json::Value> = peers.iter().map(|&peer| project(peer)).collect();
+        serde_json::json!({ "range": { "from": 0, "to": 0, "step": 1 }, "parameters": [], "sequences": [], "peers": peers }).to_string()
+    }
+
+    #[tokio::test]
+    async fn delegates_to_pinned_peers_without_the_registry() {
+        // Nothing listens on port 1, so asking the registry would fail.
+        let unreachable: SocketAddr = ([127, 0, 0, 1], 1).into();
+        let peer = peer("Foo", "[42.0]", Duration::ZERO).await;
+        let url = generator(delegating(unreachable)).await;
+        assert_eq!(post(&format!("{}/sequence/Foo", url), &pinned(&[unreachable, peer])).await, (200, "[42.0]".to_string()));
+    }
+
+    #[tokio::test]
+    async fn pinned_peers_are_not_forwarded() {
+        let peer = stub(|method, path, body| match (method, path) {
+            (&Method::GET, "/sequence") => (StatusCode::OK, r#"[{"name":"Foo"}]"#.to_string()),
+            (&Method::POST, "/sequence/Foo") if !body.contains("peers") => (StatusCode::OK, "[1.0]".to_string()),
+            _ => (StatusCode::BAD_REQUEST, String::new()),
+        })
+        .await;
+        let url = generator(delegating(([127, 0, 0, 1], 1).into())).await;
+        assert_eq!(post(&format!("{}/sequence/Foo", url), &pinned(&[peer])).await, (200, "[1.0]".to_string()));
+    }
+
+    #[test]
+    fn first_16_thue_morse_terms() {
+        assert_eq!(
+            first(ThueMorse::new(), 16),
+            [0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0],
+        );
+    }
+
+    #[tokio::test]
+    async fn gzip_response_decompresses_to_the_json() {
+        use std::io::Read;
+        let url = format!("{}/sequence/Arithmetic", generator(config()).await);
+        let client = reqwest::Client::new();
+        let body = r#"{"range": {"from": 0, "to": 999, "step": 1}, "parameters": [0, 1], "sequences": []}"#;
+        let plain = client.post(&url).body(body).send().await.unwrap();
+        assert!(plain.headers().get(CONTENT_ENCODING).is_none());
+        let plain_etag = plain.headers()[ETAG].clone();
+        let plain = plain.text().await.unwrap();
+
+        let response = client.post(&url).header(ACCEPT_ENCODING, "gzip").body(body).send().await.unwrap();
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        assert_ne!(response.headers()[ETAG], plain_etag);
+        let compressed = response.bytes().await.unwrap();
+        assert!(compressed.len() < plain.len());
+        let mut json = String::new();
+        flate2::read::GzDecoder::new(&compressed[..]).read_to_string(&mut json).unwrap();
+        assert_eq!(json, plain);
+    }
+
+    #[test]
+    fn accept_encoding_q_values() {
+        assert!(accepts_gzip("gzip"));
+        assert!(accepts_gzip("deflate, gzip;q=0.5"));
+        assert!(accepts_gzip("*"));
+        assert!(accepts_gzip("x-gzip"));
+        assert!(!accepts_gzip("gzip;q=0"));
+        assert!(!accepts_gzip("deflate, br"));
+        // An explicit gzip wins over the wildcard.
+        assert!(!accepts_gzip("gzip;q=0, *"));
+        assert!(!accepts_gzip("identity"));
+    }
+
+    #[tokio::test]
+    async fn geometric_at_2_decimals() {
+        let url = generator(config()).await;
+        let body = r#"{"range": {"from": 0, "to": 4, "step": 1}, "parameters": [1, 1.5], "sequences": []}"#;
+        let (_, full) = post(&format!("{}/sequence/Geometric", url), body).await;
+        assert_eq!(full, "[1.0,1.5,2.25,3.375,5.0625]");
+        let (_, rounded) = post(&format!("{}/sequence/Geometric?precision=2", url), body).await;
+        assert_eq!(rounded, "[1.0,1.5,2.25,3.38,5.06]");
+    }
+
+    #[test]
+    fn rounding_keeps_values_too_large_to_scale() {
+        assert_eq!(round(1e300, 10), 1e300);
+        assert_eq!(round(-2.345, 1), -2.3);
+        assert!(round(f64::NAN, 2).is_nan());
+    }
+
+    #[test]
+    fn index_is_k() {
+        let index = Index::new();
+        for k in [0, 1, 2, 1000, 123_456_789] {
+            assert_eq!(index.k_th(k), k as f64);
+        }
+    }
+
+    #[test]
+    fn missing_children_name_the_sequence_and_its_arity() {
+        let constant = || Box::new(node("Constant", &[1.0], vec![]));
+        let cases = [
+            ("Sum", vec![], vec![constant()], "Sum: Sum expects 2 sequences, got 1"),
+            ("Drop", vec![1.0], vec![], "Drop: Drop expects 1 sequences, got 0"),
+            ("LinComb", vec![1.0, 1.0, 0.0], vec![constant()], "LinComb: LinComb expects 2 sequences, got 1"),
+        ];
+        for (name, parameters, sequences, message) in cases {
+            let error = RequestError::from(validate_syntax(name, &parameters, &sequences, name).unwrap_err());
+            assert_eq!(error.status, StatusCode::BAD_REQUEST);
+            assert_eq!(error.message, message);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_children_are_400_not_a_panic() {
+        let url = generator(config()).await;
+        let (status, body) = request(&url, &node("Sum", &[], vec![]), 0, 2).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Sum expects 2 sequences, got 0"), "{}", body);
+        // Still serving afterwards.
+        assert_eq!(get(&format!("{}/ping", url)).await.0, 200);
+    }
+
+    #[test]
+    fn rolling_std_of_a_constant_and_a_ramp() {
+        assert_eq!(first(RollingStd::new(3, Constant::new(5.0)), 6), [0.0; 6]);
+        // The first two elements only have one and two elements to go on.
+        let ramp = Arithmetic::new(0.0, 1.0);
+        assert_close(&first(RollingStd::new(3, ramp), 6), &[0.0, 0.5f64.sqrt(), 1.0, 1.0, 1.0, 1.0]);
+        // At index 4 the window is 4, 9, 16.
+        let rolling = RollingStd::new(3, Prod::new(Index::new(), Index::new()));
+        let mean = 29.0 / 3.0;
+        let variance = [4.0, 9.0, 16.0].iter().map(|x: &f64| (x - mean).powi(2)).sum::<f64>() / 2.0;
+        assert_close(&rolling.range(Range { from: 4, to: 4, step: 1 }), &[variance.sqrt()]);
+    }
+
+    #[test]
+    fn rolling_std_window_must_be_at_least_1() {
+        let constant = [Box::new(node("Constant", &[5.0], vec![]))];
+        assert_eq!(RequestError::from(build_sequence("RollingStd", &[0.0], &constant).err().unwrap()).status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn rolling_std_of_nothing_is_nothing() {
+        // TakeUntil ends at once, as 0 is already above -1.
+        let empty = TakeUntil::new(-1.0, Constant::new(0.0));
+        assert!(RollingStd::new(3, empty).range(Range { from: 0, to: 3, step: 1 }).is_empty());
+    }
+
+    #[tokio::test]
+    async fn slow_headers_are_cut_off() {
+        let url = generator(Config { header_read_timeout: Duration::from_millis(200), ..config() }).await;
+        let started = Instant::now();
+        // The blank line that ends the headers never comes.
+        let partial = tokio::time::timeout(Duration::from_secs(5), raw(&url, "GET /ping HTTP/1.1\r\nHost: localhost\r\n")).await;
+        assert!(partial.is_ok(), "connection still open");
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(!partial.unwrap().contains("200 OK"));
+    }
+
+    #[tokio::test]
+    async fn idle_connections_are_closed_after_connection_timeout() {
+        let url = generator(Config { connection_timeout: Duration::from_millis(300), ..config() }).await;
+        let started = Instant::now();
+        let response = tokio::time::timeout(Duration::from_secs(5), raw(&url, "GET /ping HTTP/1.1\r\nHost: localhost\r\n\r\n")).await;
+        let response = response.expect("idle connection still open");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn without_keep_alive_the_connection_closes_after_the_response() {
+        let url = generator(Config { keep_alive: false, ..config() }).await;
+        let response = tokio::time::timeout(Duration::from_secs(2), raw(&url, "GET /ping HTTP/1.1\r\nHost: localhost\r\n\r\n")).await;
+        assert!(response.expect("connection kept open").starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn digit_sums() {
+        let expected: Vec<f64> = (0..20).map(|k| (k / 10 + k % 10) as f64).collect();
+        assert_eq!(first(DigitSum::new(), 20), expected);
+        assert_eq!(DigitSum::new().k_th(999_999), 54.0);
+    }
+
+    #[test]
+    fn first_dozen_padovan_numbers() {
+        assert_eq!(first(Padovan::new(), 12), [1.0, 1.0, 1.0, 2.0, 2.0, 3.0, 4.0, 5.0, 7.0, 9.0, 12.0, 16.0]);
+        assert_eq!(Padovan::new().range(Range { from: 9, to: 11, step: 1 }), [9.0, 12.0, 16.0]);
+    }
+
+    // Takes 10 ms per element and counts the elements it has computed.
+    struct Slow(Arc<AtomicU64>);
+
+    impl Sequence for Slow {
+        fn k_th(&self, k: usize) -> f64 {
+            std::thread::sleep(Duration::from_millis(10));
+            self.0.fetch_add(1, Ordering::Relaxed);
+            k as f64
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_evaluation_is_504_and_stops() {
+        let state = State::new(Config { evaluation_timeout: Duration::from_millis(100), ..config() });
+        let computed = Arc::new(AtomicU64::new(0));
+        let slow = Slow(computed.clone());
+        let error = run_blocking(&state, move || slow.range(Range { from: 0, to: 1000, step: 1 })).await.unwrap_err();
+        assert_eq!(error.status, StatusCode::GATEWAY_TIMEOUT);
+        // The loop notices it has been cancelled and stops well short of 1000.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let stopped_at = computed.load(Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(computed.load(Ordering::Relaxed), stopped_at);
+        assert!(stopped_at < 100);
+        assert_eq!(state.evaluations.available_permits(), state.config.max_evaluations);
+    }
+
+    #[tokio::test]
+    async fn slow_request_is_504() {
+        let url = generator(Config { evaluation_timeout: Duration::from_millis(200), max_evaluations: 1, ..config() }).await;
+        let kernel = node("Constant", &[1.0], vec![]);
+        let convolve = node("Convolve", &[1e15], vec![node("Constant", &[1.0], vec![]), kernel]);
+        assert_eq!(request(&url, &convolve, 0, 0).await.0, 504);
+        // The only evaluation slot is free again.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(request(&url, &node("Constant", &[1.0], vec![]), 0, 0).await, (200, "[1.0]".to_string()));
+    }
+
+    #[tokio::test]
+    async fn other_requests_are_answered_during_a_heavy_computation() {
+        let url = generator(Config { evaluation_timeout: Duration::from_secs(2), ..config() }).await;
+        let heavy = {
+            let url = url.clone();
+            tokio::spawn(async move {
+                let convolve = node("Convolve", &[1e15], vec![node("Constant", &[1.0], vec![]), node("Constant", &[1.0], vec![])]);
+                request(&url, &convolve, 0, 0).await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!heavy.is_finished());
+        // This test runs on a single thread, which the computation would block
+        // if it ran there.
+        let started = Instant::now();
+        assert_eq!(request(&url, &node("Constant", &[1.0], vec![]), 0, 0).await, (200, "[1.0]".to_string()));
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert_eq!(heavy.await.unwrap().0, 504);
+    }
+
+    // Body of a compare request for the two trees over from..=to.
+    fn compare_body(first: &SequenceSyntax, second: &SequenceSyntax, from: u64, to: u64, step: u64) -> String {
+        let item = |syntax: &SequenceSyntax| serde_json::json!({
+            "name": syntax.name,
+            "request": { "range": { "from": 0, "to": 0, "step": 1 }, "parameters": syntax.parameters, "sequences": syntax.sequences },
+        });
+        serde_json::json!({ "first": item(first), "second": item(second), "range": { "from": from, "to": to, "step": step } }).to_string()
+    }
+
+    #[tokio::test]
+    async fn compare_finds_equivalent_constructions_equal() {
+        let url = format!("{}/sequence/compare", generator(config()).await);
+        let geometric = || node("Geometric", &[1.0, 2.0], vec![]);
+        let negate = node("Negate", &[], vec![geometric()]);
+        let scaled = node("LinComb", &[-1.0, 0.0, 0.0], vec![geometric(), node("Constant", &[0.0], vec![])]);
+        assert_eq!(post(&url, &compare_body(&negate, &scaled, 0, 20, 1)).await, (200, r#"{"equal":true,"first_divergence":null}"#.to_string()));
+    }
+
+    #[tokio::test]
+    async fn compare_reports_the_index_of_the_first_divergence() {
+        let url = format!("{}/sequence/compare", generator(config()).await);
+        let index = node("Index", &[], vec![]);
+        let cut = node("Piecewise", &[5.0], vec![node("Index", &[], vec![]), node("Constant", &[0.0], vec![])]);
+        let (_, body) = post(&url, &compare_body(&index, &cut, 1, 11, 2)).await;
+        assert_eq!(body, r#"{"equal":false,"first_divergence":5}"#);
+    }
+
+    #[tokio::test]
+    async fn compare_fails_on_an_invalid_result() {
+        let peer = peer("Foo", "not a sequence", Duration::ZERO).await;
+        let url = format!("{}/sequence/compare", generator(delegating(([127, 0, 0, 1], 1).into())).await);
+        let mut body: serde_json::Value = serde_json::from_str(&compare_body(&node("Foo", &[], vec![]), &node("Index", &[], vec![]), 0, 3, 1)).unwrap();
+        body["first"]["request"]["peers"] = serde_json::json!([project(peer)]);
+        let (status, body) = post(&url, &body.to_string()).await;
+        assert_eq!(status, 502);
+        assert!(body.contains("Got an invalid result for Foo"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn project_id_is_stable() {
+        let project = get_project();
+        assert_eq!(project.id.as_deref(), Some("Lucija & Lev@127.0.0.1:12346"));
+        assert_eq!(get_project().id, project.id);
+
+        // Registering again sends the same id, so the registry can replace the entry.
+        let (registry, projects) = registry(Vec::new()).await;
+        let state = State::new(delegating(registry));
+        register(&state).await.unwrap();
+        register(&state).await.unwrap();
+        let projects = projects.lock().unwrap();
+        assert_eq!(projects.len(), 2);
+        assert_eq!(projects[0]["id"], projects[1]["id"]);
+    }
+
+    #[test]
+    fn arithmetic_at_negative_indices() {
+        let arithmetic = Arithmetic::new(1.0, 2.0);
+        assert_eq!(arithmetic.range_signed(SignedRange { from: -3, to: 2, step: 1 }), [-5.0, -3.0, -1.0, 1.0, 3.0, 5.0]);
+        assert_eq!(arithmetic.range_signed(SignedRange { from: -4, to: 4, step: 4 }), [-7.0, 1.0, 9.0]);
+        assert_eq!(arithmetic.k_th_signed(i64::MIN), 1.0 + 2.0 * i64::MIN as f64);
+    }
+
+    #[test]
+    fn drop_shifts_negative_indices_and_others_have_none() {
+        let drop = Drop::new(Arithmetic::new(0.0, 1.0), 2);
+        assert_eq!(drop.range_signed(SignedRange { from: -2, to: 0, step: 1 }), [0.0, 1.0, 2.0]);
+        // Catalan keeps the default, which has nothing below index 0.
+        let values = Catalan::new().range_signed(SignedRange { from: -1, to: 2, step: 1 });
+        assert!(values[0].is_nan());
+        assert_eq!(values[1..], [1.0, 1.0, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn signed_range_request() {
+        let url = generator(config()).await;
+        let body = r#"{"range": {"from": 0, "to": 0, "step": 1}, "signed_range": {"from": -2, "to": 1, "step": 1}, "parameters": [0, 1], "sequences": []}"#;
+        assert_eq!(post(&format!("{}/sequence/Arithmetic", url), body).await, (200, "[-2.0,-1.0,0.0,1.0]".to_string()));
+        let too_big = body.replace(r#""step": 1}, "par"#, &format!(r#""step": {}}}, "par"#, u64::MAX));
+        assert_eq!(post(&format!("{}/sequence/Arithmetic", url), &too_big).await.0, 400);
+    }
+
+    #[tokio::test]
+    async fn range_only_sequences_take_no_signed_range() {
+        let url = generator(config()).await;
+        let reverse = node("Reverse", &[], vec![node("Index", &[], vec![])]);
+        let body = request_body(&reverse, 0, 0).replacen('{', r#"{"signed_range": {"from": -2, "to": 2, "step": 1}, "#, 1);
+        let (status, body) = post(&format!("{}/sequence/Reverse", url), &body).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Reverse is only defined over a range and can't be given a signed range"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn compare_items_are_evaluated_over_its_range_only() {
+        let url = format!("{}/sequence/compare", generator(config()).await);
+        let index = node("Index", &[], vec![]);
+        let body: serde_json::Value = serde_json::from_str(&compare_body(&index, &index, 0, 3, 1)).unwrap();
+        for (field, value) in [("indices", serde_json::json!([0, 9])), ("signed_range", serde_json::json!({ "from": -1, "to": 2, "step": 1 }))] {
+            let mut body = body.clone();
+            body["second"]["request"][field] = value;
+            let (status, body) = post(&url, &body.to_string()).await;
+            assert_eq!(status, 400);
+            assert!(body.contains("can't have a signed_range or indices"), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn fastest_peer_is_preferred() {
+        let slow = peer("Foo", "[1.0]", Duration::from_millis(200)).await;
+        let fast = peer("Foo", "[2.0]", Duration::ZERO).await;
+        let (registry, _) = registry(vec![project(slow), project(fast)]).await;
+        let url = generator(Config { prefer_fastest_peer: true, ..delegating(registry) }).await;
+        for _ in 0..5 {
+            assert_eq!(post(&format!("{}/sequence/Foo", url), FOO).await, (200, "[2.0]".to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn peers_are_pinged_at_once_and_unreachable_ones_go_last() {
+        let slow = peer("Foo", "[1.0]", Duration::from_millis(300)).await;
+        let slower = peer("Foo", "[1.0]", Duration::from_millis(300)).await;
+        let addresses = [slow, ([127, 0, 0, 1], 1).into(), slower].map(|addr: SocketAddr| addr.to_string());
+        let state = State::new(config());
+        let started = Instant::now();
+        let latencies = peer_latencies(&addresses, &state).await;
+        assert!(started.elapsed() < Duration::from_millis(550), "pinged one after another");
+        assert!(latencies[0] >= Duration::from_millis(300) && latencies[0] < Duration::MAX);
+        assert_eq!(latencies[1], Duration::MAX);
+        // Measured latencies are reused for a while.
+        let started = Instant::now();
+        assert_eq!(peer_latencies(&addresses, &state).await, latencies);
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn first_12_divisor_counts() {
+        assert_eq!(first(DivisorCount::new(), 12), [0.0, 1.0, 2.0, 2.0, 3.0, 2.0, 4.0, 2.0, 4.0, 3.0, 4.0, 2.0]);
+        let divisors = DivisorCount::new();
+        // 2^4 * 3^2 * 5 has 5 * 3 * 2 divisors, and a square counts its root once.
+        assert_eq!(divisors.k_th(720), 30.0);
+        assert_eq!(divisors.k_th(1_000_000), 49.0);
+    }
+
+    #[test]
+    fn normalize_maps_the_range_onto_0_to_1() {
+        let normalize = Normalize::new(Arithmetic::new(3.0, -2.0));
+        assert_eq!(normalize.range(Range { from: 10, to: 14, step: 1 }), [1.0, 0.75, 0.5, 0.25, 0.0]);
+        assert_eq!(normalize.range(Range { from: 0, to: 8, step: 4 }), [1.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn normalizing_a_constant_gives_zeros() {
+        assert_eq!(first(Normalize::new(Constant::new(7.0)), 4), [0.0; 4]);
+    }
+
+    #[test]
+    fn normalize_only_works_over_a_range() {
+        let normalize = node("Normalize", &[], vec![node("Constant", &[7.0], vec![])]);
+        let smoothed = node("Smoothed", &[], vec![normalize]);
+        let error = validate_syntax(&smoothed.name, &smoothed.parameters, &smoothed.sequences, &smoothed.name).unwrap_err();
+        assert_eq!(error.to_string(), "Smoothed.sequences[0]: Normalize is only defined over a range and can't be used inside Smoothed");
+    }
+
+    // Writes contents to a config file of its own and reads it back.
+    fn read_config(name: &str, contents: &str) -> Result<ConfigFile, Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join(format!("generator-{}-{}.json", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        let file = ConfigFile::read(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+
+    #[test]
+    fn loads_a_sample_config() {
+        let file = read_config("sample", r#"{
+            "registry_url": "http://registry.local:8080",
+            "body_limit": 1024,
+            "evaluation_timeout": 2,
+            "http2": true,
+            "max_batch_items": 3,
+            "rate_limit": 5.5
+        }"#).unwrap();
+        let config = Config::load(file).unwrap();
+        assert_eq!(config.projects_url(), "http://registry.local:8080/project");
+        assert_eq!(config.body_limit, 1024);
+        assert_eq!(config.evaluation_timeout, Duration::from_secs(2));
+        assert!(config.http2);
+        assert_eq!(config.max_batch_items, 3);
+        assert_eq!(config.rate_limit, 5.5);
+        // Everything else keeps its default.
+        assert_eq!(config.max_range_len, MAX_RANGE_LEN);
+        assert_eq!(config.cors_origin, "*");
+    }
+
+    #[test]
+    fn bad_config_files_are_errors() {
+        assert!(read_config("unknown", r#"{"body_limt": 1024}"#).is_err());
+        assert!(read_config("type", r#"{"body_limit": "big"}"#).is_err());
+        assert!(ConfigFile::read("/nonexistent/generator.json").is_err());
+        let zero = read_config("zero", r#"{"max_evaluations": 0}"#).unwrap();
+        assert_eq!(Config::load(zero).unwrap_err(), "MAX_EVALUATIONS must be positive");
+        let burst = read_config("burst", r#"{"rate_limit": 1, "rate_burst": 0.5}"#).unwrap();
+        assert_eq!(Config::load(burst).unwrap_err(), "RATE_BURST must be at least 1");
+    }
+
+    #[tokio::test]
+    async fn ready_once_registered() {
+        // The registry refuses registrations until it is switched on.
+        let up = Arc::new(AtomicBool::new(false));
+        let on = up.clone();
+        let registry = stub(move |_, _, _| match on.load(Ordering::Relaxed) {
+            true => (StatusCode::OK, "[]".to_string()),
+            false => (StatusCode::SERVICE_UNAVAILABLE, String::new()),
+        })
+        .await;
+        let config = Config { heartbeat_interval: Duration::from_millis(10), ..delegating(registry) };
+        let state: Shared = Arc::new(State::new(config));
+        let url = serving(state.clone()).await;
+        assert_eq!(get(&format!("{}/ready", url)).await.0, 503);
+
+        join_registry(&state).await;
+        assert_eq!(get(&format!("{}/ready", url)).await.0, 503);
+        // /health answers either way.
+        assert_eq!(get(&format!("{}/health", url)).await.0, 200);
+
+        // The heartbeat gets through once the registry is up.
+        up.store(true, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(get(&format!("{}/ready", url)).await.0, 200);
+    }
+
+    #[tokio::test]
+    async fn sequence_list_names_the_parameters() {
+        let url = generator(config()).await;
+        let (status, body) = get(&format!("{}/sequence", url)).await;
+        assert_eq!(status, 200);
+        let list: Vec<SequenceInfo> = serde_json::from_str(&body).unwrap();
+        let arithmetic = list.iter().find(|info| info.name == "Arithmetic").unwrap();
+        assert_eq!(arithmetic.parameter_names, ["start", "step"]);
+        assert_eq!(arithmetic.parameter_descriptions, ["First element", "Difference between consecutive elements"]);
+        assert_eq!(arithmetic.parameters, 2);
+
+        // Lists from older peers come without them.
+        let old: SequenceInfo = serde_json::from_str(r#"{"name":"Foo","description":"","parameters":1,"sequences":0}"#).unwrap();
+        assert!(old.parameter_names.is_empty() && old.parameter_descriptions.is_empty());
+    }
+
+    #[test]
+    fn limit_of_a_few_terms() {
+        let limit = estimate_limit(&[3.0, 2.0, 1.5, 1.5, 1.5], 0.1);
+        assert!(limit.converges);
+        assert_eq!((limit.limit, limit.settled_at), (Some(1.5), Some(2)));
+        // Still moving at the last term.
+        assert!(!estimate_limit(&[3.0, 2.0, 1.0], 0.1).converges);
+        assert!(!estimate_limit(&[1.0, f64::INFINITY, 1.0, 1.0], 0.1).converges);
+    }
+
+    #[tokio::test]
+    async fn limit_of_geometric_and_arithmetic() {
+        let url = generator(config()).await;
+        let limit = |name: &str| format!("{}/sequence/{}/limit", url, name);
+
+        let (status, body) = post(&limit("Geometric"), r#"{"parameters":[8.0,0.5],"sequences":[],"tolerance":1e-6,"max_iterations":100}"#).await;
+        assert_eq!(status, 200);
+        let geometric: Limit = serde_json::from_str(&body).unwrap();
+        assert!(geometric.converges);
+        assert!(geometric.limit.unwrap().abs() < 1e-6, "{}", body);
+        // 8 * 0.5^k - 8 * 0.5^(k+1) = 0.5^(k-2) drops below 1e-6 from k = 22.
+        assert_eq!(geometric.settled_at, Some(22));
+
+        let (status, body) = post(&limit("Arithmetic"), r#"{"parameters":[0.0,1.0],"sequences":[]}"#).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"converges":false}"#);
+
+        let (status, _) = post(&limit("Arithmetic"), r#"{"parameters":[0.0,1.0],"sequences":[],"tolerance":0}"#).await;
+        assert_eq!(status, 400);
+        let (status, _) = post(&limit("Arithmetic"), r#"{"parameters":[0.0,1.0],"sequences":[],"max_iterations":1}"#).await;
+        assert_eq!(status, 400);
+        let (status, _) = post(&format!("{}/sequence/Arithmetic/limit/limit", url), r#"{"parameters":[0.0,1.0],"sequences":[]}"#).await;
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn running_product_of_twos_is_powers_of_two() {
+        let twos = || RunningProduct::new(Constant::new(2.0));
+        assert_eq!(first(twos(), 6), [2.0, 4.0, 8.0, 16.0, 32.0, 64.0]);
+        // A window later on gives the same terms as k_th.
+        let seq = twos();
+        assert_eq!(seq.range(Range { from: 10, to: 14, step: 2 }), [seq.k_th(10), seq.k_th(12), seq.k_th(14)]);
+        assert_eq!(seq.k_th(10), 2048.0);
+    }
+
+    #[test]
+    fn running_product_of_one_two_three_is_factorial() {
+        assert_eq!(first(RunningProduct::new(Arithmetic::new(1.0, 1.0)), 5), [1.0, 2.0, 6.0, 24.0, 120.0]);
+    }
+
+    #[test]
+    fn unknown_sequence_is_a_build_error() {
+        let e = build_sequence("Foo", &[], &[]).err().unwrap();
+        assert!(matches!(&e, BuildError::UnknownSequence(name) if name == "Foo"));
+        assert_eq!(RequestError::from(e).status, StatusCode::NOT_FOUND);
+        // Also below one of ours.
+        let sum = node("Sum", &[], vec![node("Constant", &[1.0], vec![]), node("Foo", &[], vec![])]);
+        assert!(matches!(build_sequence(&sum.name, &sum.parameters, &sum.sequences), Err(BuildError::UnknownSequence(_))));
+    }
+
+    #[tokio::test]
+    async fn wrong_param_count_is_a_build_error() {
+        let geometric = node("Geometric", &[1.0], vec![]);
+        let e = validate_syntax(&geometric.name, &geometric.parameters, &geometric.sequences, "Geometric").unwrap_err();
+        assert!(matches!(e, BuildError::WrongParamCount { expected: 2, at_least: false, got: 1, .. }));
+
+        let url = generator(config()).await;
+        let nested = node("Negate", &[], vec![geometric]);
+        let (status, body) = request(&url, &nested, 0, 0).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Negate.sequences[0]: Geometric expects 2 parameters, got 1"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn wrong_child_count_is_a_build_error() {
+        let negate = node("Negate", &[], vec![]);
+        let e = validate_syntax(&negate.name, &negate.parameters, &negate.sequences, "Negate").unwrap_err();
+        assert!(matches!(e, BuildError::WrongChildCount { expected: 1, got: 0, .. }));
+
+        let url = generator(config()).await;
+        let (status, body) = request(&url, &negate, 0, 0).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Negate: Negate expects 1 sequences, got 0"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn invalid_parameters_are_a_build_error() {
+        let modulo = node("Modulo", &[0.0], vec![node("Index", &[], vec![])]);
+        assert!(matches!(build_sequence(&modulo.name, &modulo.parameters, &modulo.sequences), Err(BuildError::InvalidParameters(_))));
+
+        let url = generator(config()).await;
+        assert_eq!(request(&url, &modulo, 0, 0).await.0, 400);
+        let cyclic = node("Cyclic", &[0.0], vec![node("Index", &[], vec![])]);
+        let (status, body) = request(&url, &cyclic, 0, 0).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Cyclic cycle_length must be at least 1"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn range_only_is_a_build_error() {
+        let normalize = node("Normalize", &[], vec![node("Index", &[], vec![])]);
+        let negate = node("Negate", &[], vec![normalize]);
+        let e = validate_syntax(&negate.name, &negate.parameters, &negate.sequences, "Negate").unwrap_err();
+        assert!(matches!(&e, BuildError::RangeOnly { name, parent, .. } if name == "Normalize" && parent == "Negate"));
+
+        let url = generator(config()).await;
+        let (status, body) = request(&url, &negate, 0, 0).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("only defined over a range"), "{}", body);
+    }
+
+    #[test]
+    fn fibonacci_sample_of_index_is_fibonacci() {
+        let fibonacci = || FibonacciSample::new(Index::new());
+        assert_eq!(first(fibonacci(), 10), [0.0, 1.0, 1.0, 2.0, 3.0, 5.0, 8.0, 13.0, 21.0, 34.0]);
+        let seq = fibonacci();
+        assert_eq!(seq.k_th(93), 12_200_160_415_121_876_738u64 as f64);
+        // F(94) is past any index.
+        assert!(seq.k_th(94).is_nan());
+    }
+
+    #[test]
+    fn fibonacci_sample_of_evens() {
+        assert_eq!(first(FibonacciSample::new(Arithmetic::new(0.0, 2.0)), 6), [0.0, 2.0, 2.0, 4.0, 6.0, 10.0]);
+    }
+
+    #[tokio::test]
+    async fn head_is_get_without_the_body() {
+        let url = generator(config()).await;
+        for path in ["/sequence", "/ping"] {
+            let (_, body) = get(&format!("{}{}", url, path)).await;
+            let response = reqwest::Client::new().head(format!("{}{}", url, path)).send().await.unwrap();
+            assert_eq!(response.status(), 200);
+            assert_eq!(response.headers()[CONTENT_LENGTH], body.len().to_string().as_str());
+            assert_eq!(response.text().await.unwrap(), "");
+        }
+        let response = reqwest::Client::new().head(format!("{}/nonexistent", url)).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn delegation_asks_at_most_max_delegation_peers() {
+        // Peers without Foo, counting the ones that are asked for their list.
+        let asked = Arc::new(AtomicU64::new(0));
+        let mut peers = Vec::new();
+        for _ in 0..5 {
+            let asked = asked.clone();
+            peers.push(project(stub(move |method, path, _| match (method, path) {
+                (&Method::GET, "/sequence") => {
+                    asked.fetch_add(1, Ordering::Relaxed);
+                    (StatusCode::OK, "[]".to_string())
+                }
+                _ => (StatusCode::OK, "{}".to_string()),
+            })
+            .await));
+        }
+        let (registry, _) = registry(peers).await;
+
+        for prefer_fastest_peer in [false, true] {
+            asked.store(0, Ordering::Relaxed);
+            let url = generator(Config { max_delegation_peers: 2, prefer_fastest_peer, ..delegating(registry) }).await;
+            assert_eq!(post(&format!("{}/sequence/Foo", url), FOO).await.0, 404);
+            assert_eq!(asked.load(Ordering::Relaxed), 2);
+        }
+
+        // By default every peer is tried.
+        asked.store(0, Ordering::Relaxed);
+        let url = generator(delegating(registry)).await;
+        assert_eq!(post(&format!("{}/sequence/Foo", url), FOO).await.0, 404);
+        assert_eq!(asked.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn step_at_three() {
+        assert_eq!(first(Step::new(3.0), 6), [0.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+        assert_eq!(first(Step::new(0.0), 3), [1.0; 3]);
+        // Between two indices it steps at the next one.
+        assert_eq!(first(Step::new(1.5), 3), [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn impulse_at_two() {
+        assert_eq!(first(Impulse::new(2.0), 5), [0.0, 0.0, 1.0, 0.0, 0.0]);
+        // No index is ever 1.5.
+        assert_eq!(first(Impulse::new(1.5), 3), [0.0; 3]);
+    }
+
+    #[test]
+    fn convolving_with_an_impulse_shifts() {
+        let convolved = Convolve::new(4, Arithmetic::new(10.0, 1.0), Impulse::new(2.0));
+        // Indices before 0 read element 0.
+        assert_eq!(first(convolved, 6), [10.0, 10.0, 10.0, 11.0, 12.0, 13.0]);
+    }
+
+    #[test]
+    fn find_sequence_ignores_case() {
+        let seqs = sequences();
+        assert_eq!(find_sequence(&seqs, "/sequence/arithmetic").unwrap().name, "Arithmetic");
+        assert_eq!(find_sequence(&seqs, "/sequence/ARITHMETIC").unwrap().name, "Arithmetic");
+        assert!(find_sequence(&seqs, "/sequence/Arithmetik").is_none());
+        assert!(find_sequence(&seqs, "/sequences/Arithmetic").is_none());
+    }
+
+    #[tokio::test]
+    async fn routes_ignore_trailing_slash_and_case() {
+        let url = generator(config()).await;
+        let body = request_body(&node("Arithmetic", &[0.0, 1.0], vec![]), 0, 2);
+        for path in ["Arithmetic", "Arithmetic/", "arithmetic", "ARITHMETIC/"] {
+            assert_eq!(post(&format!("{}/sequence/{}", url, path), &body).await, (200, "[0.0,1.0,2.0]".to_string()), "{}", path);
+        }
+        assert_eq!(get(&format!("{}/sequence/", url)).await.0, 200);
+        let limit = r#"{"parameters":[0.0,1.0],"sequences":[]}"#;
+        assert_eq!(post(&format!("{}/sequence/arithmetic/limit/", url), limit).await.0, 200);
+
+        let (status, body) = post(&format!("{}/sequence/Arithmetik/limit", url), limit).await;
+        assert_eq!(status, 404);
+        assert!(body.contains("No sequence named Arithmetik"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn names_in_the_body_ignore_case() {
+        let url = generator(config()).await;
+        let range = serde_json::json!({ "from": 0, "to": 1, "step": 1 });
+        let syntax = serde_json::json!({ "name": "arithmetic", "parameters": [0.0, 1.0], "sequences": [] });
+        let evaluate = serde_json::json!({ "range": range, "syntax": syntax });
+        assert_eq!(post(&format!("{}/evaluate", url), &evaluate.to_string()).await, (200, "[0.0,1.0]".to_string()));
+
+        let item = serde_json::json!({ "name": "ARITHMETIC", "request": { "range": range, "parameters": [0.0, 1.0], "sequences": [] } });
+        let batch = serde_json::json!([item]);
+        assert_eq!(post(&format!("{}/sequence/batch", url), &batch.to_string()).await, (200, "[[0.0,1.0]]".to_string()));
+        let compare = serde_json::json!({ "first": item, "second": item, "range": range });
+        assert_eq!(post(&format!("{}/sequence/compare", url), &compare.to_string()).await, (200, r#"{"equal":true,"first_divergence":null}"#.to_string()));
+    }
+
+    #[test]
+    fn parses_nested_expressions() {
+        let syntax = Parser::parse("Sum(Arithmetic(0, 1), Prod( Geometric(1,2) , Index ), -2.5e1)", 10).unwrap();
+        assert_eq!(
+            serde_json::to_value(&syntax).unwrap(),
+            serde_json::json!({
+                "name": "Sum",
+                "parameters": [-25.0],
+                "sequences": [
+                    { "name": "Arithmetic", "parameters": [0.0, 1.0], "sequences": [] },
+                    { "name": "Prod", "parameters": [], "sequences": [
+                        { "name": "Geometric", "parameters": [1.0, 2.0], "sequences": [] },
+                        { "name": "Index", "parameters": [], "sequences": [] },
+                    ] },
+                ],
+            })
+        );
+        assert_eq!(Parser::parse("Index()", 1).unwrap().name, "Index");
+    }
+
+    #[test]
+    fn parse_errors_say_where() {
+        assert_eq!(Parser::parse("Sum(Index Index)", 10).unwrap_err(), "Expected ',' or ')' at position 10, found 'I'");
+        assert_eq!(Parser::parse("Sum(Index", 10).unwrap_err(), "Expected ',' or ')' at position 9, found end of input");
+        assert_eq!(Parser::parse("Index) ", 10).unwrap_err(), "Expected end of input at position 5, found ')'");
+        assert_eq!(Parser::parse("(1)", 10).unwrap_err(), "Expected a sequence name at position 0, found '('");
+        assert_eq!(Parser::parse("Constant(1.2.3)", 10).unwrap_err(), "Invalid number 1.2.3 at position 9");
+        assert_eq!(Parser::parse("Negate(Negate(Index))", 2).unwrap_err(), "Sequences nested deeper than 2 at position 14");
+    }
+
+    #[tokio::test]
+    async fn parse_endpoint() {
+        let url = generator(config()).await;
+        let (status, body) = post(&format!("{}/parse", url), "Negate(Constant(3))").await;
+        assert_eq!(status, 200);
+        // What comes back can be asked for as is.
+        let syntax: SequenceSyntax = serde_json::from_str(&body).unwrap();
+        assert_eq!(request(&url, &syntax, 0, 1).await, (200, "[-3.0,-3.0]".to_string()));
+
+        let (status, body) = post(&format!("{}/parse", url), "Negate(Constant(3)").await;
+        assert_eq!(status, 400);
+        assert!(body.contains("found end of input"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn evaluates_at_explicit_indices() {
+        let url = generator(Config { max_range_len: 3, ..config() }).await;
+        for (indices, expected) in [
+            ("[0,5,100]", "[3.0,13.0,203.0]"),
+            // In the order given, repeats included.
+            ("[5,0,5]", "[13.0,3.0,13.0]"),
+            ("[]", "[]"),
+        ] {
+            // A range that would be rejected on its own; indices take precedence.
+            let body = format!(r#"{{"range":{{"from":0,"to":99,"step":0}},"parameters":[3.0,2.0],"sequences":[],"indices":{}}}"#, indices);
+            assert_eq!(post(&format!("{}/sequence/Arithmetic", url), &body).await, (200, expected.to_string()));
+        }
+        let reverse = node("Reverse", &[], vec![node("Index", &[], vec![])]);
+        let body = request_body(&reverse, 0, 0).replacen('{', r#"{"indices": [0, 1], "#, 1);
+        let (status, body) = post(&format!("{}/sequence/Reverse", url), &body).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Reverse is only defined over a range and can't be evaluated at a list of indices"), "{}", body);
+        // max_range_len limits the number of indices rather than how far out they are.
+        let body = r#"{"range":{"from":0,"to":0,"step":1},"parameters":[3.0,2.0],"sequences":[],"indices":[0,1,2,3]}"#;
+        let (status, body) = post(&format!("{}/sequence/Arithmetic", url), body).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Range has 4 elements, at most 3 allowed"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn trees_deeper_than_max_depth_are_rejected() {
+        assert_eq!(depth(&nested(5).sequences), 5);
+        let url = generator(Config { max_depth: 5, ..config() }).await;
+        assert_eq!(request(&url, &nested(5), 0, 0).await, (200, "[0.0]".to_string()));
+        let (status, body) = request(&url, &nested(6), 0, 0).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Sequences are nested 6 deep, at most 5 allowed"), "{}", body);
+
+        // Past serde_json's recursion limit the JSON itself is refused,
+        // whatever max_depth says.
+        let url = generator(Config { max_depth: 1000, ..config() }).await;
+        let deep = format!(
+            r#"{{"range":{{"from":0,"to":0,"step":1}},"parameters":[],"sequences":[{}{}]}}"#,
+            r#"{"name":"Negate","parameters":[],"sequences":["#.repeat(200),
+            "]}".repeat(200),
+        );
+        let (status, body) = post(&format!("{}/sequence/Negate", url), &deep).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("recursion limit"), "{}", body);
+    }
+
+    #[test]
+    fn evaluates_the_deepest_trees_json_allows() {
+        // On a runtime like the one main builds. Past 63 levels the body
+        // exceeds serde_json's recursion limit, so 63 is as deep as trees get.
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_stack_size(THREAD_STACK_SIZE)
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let url = generator(Config { max_depth: 1000, ..config() }).await;
+            for levels in [MAX_DEPTH, 63] {
+                // Index under levels - 1 Negates.
+                let negated = if levels % 2 == 0 { "[-1.0,-2.0]" } else { "[1.0,2.0]" };
+                assert_eq!(request(&url, &nested(levels), 1, 2).await, (200, negated.to_string()), "{}", levels);
+            }
+            assert_eq!(request(&url, &nested(64), 1, 2).await.0, 400);
+        });
+    }
+
+    #[tokio::test]
+    async fn unknown_sequences_are_404() {
+        let url = generator(config()).await;
+        assert_eq!(post(&format!("{}/sequence/Nope", url), FOO).await.0, 404);
+        assert_eq!(get(&format!("{}/sequence/Nope", url)).await.0, 404);
+
+        let (registry, _) = registry(Vec::new()).await;
+        let url = generator(delegating(registry)).await;
+        let sum = node("Sum", &[], vec![node("Nope", &[], vec![]), node("Constant", &[1.0], vec![])]);
+        let (status, body) = request(&url, &sum, 0, 2).await;
+        assert_eq!(status, 404);
+        assert!(body.contains("Nobody has Sum"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn delegation_finds_the_peer_advertising_the_bare_name() {
+        let bar = peer("Bar", "[1.0]", Duration::ZERO).await;
+        let foo = peer("Foo", "[2.0]", Duration::ZERO).await;
+        let (registry, _) = registry(vec![project(bar), project(foo)]).await;
+        let state = State::new(delegating(registry));
+        let request: SequenceRequest = serde_json::from_str(FOO).unwrap();
+        assert_eq!(delegate(request, "Foo", &state).await.unwrap().as_deref(), Some("[2.0]"));
+        // Neither advertises the name with the path in front of it.
+        let request: SequenceRequest = serde_json::from_str(FOO).unwrap();
+        assert_eq!(delegate(request, "/sequence/Foo", &state).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn delegation_skips_this_node() {
+        let foo = peer("Foo", "[2.0]", Duration::ZERO).await;
+        let this = serde_json::json!({ "name": "us", "ip": "127.0.0.1", "port": PORT.to_string() });
+        let (registry, _) = registry(vec![this, project(foo)]).await;
+        // Asking ourselves would use up the only try.
+        let url = generator(Config { max_delegation_peers: 1, ..delegating(registry) }).await;
+        for _ in 0..5 {
+            assert_eq!(post(&format!("{}/sequence/Foo", url), FOO).await, (200, "[2.0]".to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn request_id_is_forwarded_when_delegating() {
+        let peer = stub(|method, path, body| match (method, path) {
+            (&Method::GET, "/sequence") => (StatusCode::OK, r#"[{"name":"Foo"}]"#.to_string()),
+            (&Method::POST, "/sequence/Foo") if body.contains(r#""request_id":"abc-123""#) => (StatusCode::OK, "[1.0]".to_string()),
+            _ => (StatusCode::BAD_REQUEST, String::new()),
+        })
+        .await;
+        let (registry, _) = registry(vec![project(peer)]).await;
+        let url = generator(delegating(registry)).await;
+        let body = r#"{"range": {"from": 0, "to": 0, "step": 1}, "parameters": [], "sequences": [], "request_id": "abc-123"}"#;
+        assert_eq!(post(&format!("{}/sequence/Foo", url), body).await, (200, "[1.0]".to_string()));
+    }
+
+    #[tokio::test]
+    async fn starts_without_a_registry() {
+        // One that never answers in time, and none at all.
+        let silent = stub_with_delay(Duration::from_secs(60), |_, _, _| (StatusCode::OK, "[]".to_string())).await;
+        for registry in [silent, ([127, 0, 0, 1], 1).into()] {
+            let config = Config { registry_timeout: Duration::from_millis(100), ..delegating(registry) };
+            let state: Shared = Arc::new(State::new(config.clone()));
+            tokio::time::timeout(Duration::from_secs(2), join_registry(&state)).await.unwrap();
+            assert!(!state.status.lock().unwrap().registered);
+
+            let url = generator(config).await;
+            assert_eq!(request(&url, &node("Constant", &[1.0], vec![]), 0, 0).await, (200, "[1.0]".to_string()));
+            assert_eq!(get(&format!("{}/ready", url)).await.0, 503);
+        }
+    }
+
+    #[tokio::test]
+    async fn registers_with_the_registry() {
+        let (registry, projects) = registry(Vec::new()).await;
+        let state = State::new(delegating(registry));
+        register(&state).await.unwrap();
+        assert_eq!(projects.lock().unwrap()[0]["name"], get_project().name);
+        assert!(state.status.lock().unwrap().ever_registered);
+    }
+
+    #[tokio::test]
+    async fn delegates_to_a_peer_from_the_registry() {
+        let peer = peer("Foo", "[42.0]", Duration::ZERO).await;
+        let (registry, _) = registry(vec![project(peer)]).await;
+        let url = generator(delegating(registry)).await;
+        assert_eq!(post(&format!("{}/sequence/Foo", url), FOO).await, (200, "[42.0]".to_string()));
+    }
+
+    #[tokio::test]
+    async fn nobody_has_a_sequence_no_peer_has() {
+        let peer = peer("Foo", "[42.0]", Duration::ZERO).await;
+        let (registry, _) = registry(vec![project(peer)]).await;
+        let url = generator(delegating(registry)).await;
+        assert_eq!(post(&format!("{}/sequence/Bar", url), FOO).await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn peers_skips_bad_registry_entries_and_asks_at_once() {
+        let slow = peer("Foo", "[]", Duration::from_millis(300)).await;
+        let also_slow = peer("Bar", "[]", Duration::from_millis(300)).await;
+        let (registry, _) = registry(vec![
+            project(slow),
+            serde_json::json!({ "name": "peer", "ip": "127.0.0.1", "port": also_slow.port().to_string() }),
+            serde_json::json!({ "name": "us", "ip": "127.0.0.1", "port": PORT.to_string() }),
+            serde_json::json!({ "name": "broken", "ip": "127.0.0.1" }),
+        ])
+        .await;
+        let url = generator(delegating(registry)).await;
+        let started = Instant::now();
+        let (status, body) = get(&format!("{}/peers", url)).await;
+        assert!(started.elapsed() < Duration::from_millis(550), "{:?}", started.elapsed());
+        assert_eq!(status, 200);
+        let peers: BTreeMap<String, Vec<String>> = serde_json::from_str(&body).unwrap();
+        assert_eq!(peers, BTreeMap::from([(slow.to_string(), vec!["Foo".to_string()]), (also_slow.to_string(), vec!["Bar".to_string()])]));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_registry_without_pinned_peers() {
+        let (registry, _) = registry(Vec::new()).await;
+        let url = generator(delegating(registry)).await;
+        assert_eq!(post(&format!("{}/sequence/Foo", url), FOO).await.0, 404);
+        let url = generator(delegating(([127, 0, 0, 1], 1).into())).await;
+        assert_eq!(post(&format!("{}/sequence/Foo", url), FOO).await.0, 503);
+    }
+
+    #[tokio::test]
+    async fn capabilities_match_the_config() {
+        let config = Config {
+            max_range_len: 10,
+            body_limit: 256,
+            max_depth: 4,
+            max_evaluations: 3,
+            evaluation_timeout: Duration::from_secs(7),
+            ..config()
+        };
+        let url = generator(config).await;
+        let (status, body) = get(&format!("{}/capabilities", url)).await;
+        assert_eq!(status, 200);
+        let capabilities: Capabilities = serde_json::from_str(&body).unwrap();
+        assert_eq!(capabilities.content_types, ["application/json"]);
+        assert_eq!(capabilities.encodings, ["gzip"]);
+        assert!(!capabilities.streaming && !capabilities.http2 && !capabilities.delegation);
+        assert_eq!(capabilities.max_range_len, 10);
+        assert_eq!(capabilities.body_limit, 256);
+        assert_eq!(capabilities.max_depth, 4);
+        assert_eq!(capabilities.max_evaluations, 3);
+        assert_eq!(capabilities.evaluation_timeout_secs, 7);
+        assert_eq!(capabilities.max_batch_items, MAX_BATCH_ITEMS);
+
+        // And they are the limits that apply.
+        let index = node("Index", &[], vec![]);
+        assert_eq!(request(&url, &index, 0, 9).await.0, 200);
+        assert_eq!(request(&url, &index, 0, 10).await.0, 400);
+        assert_eq!(post(&format!("{}/sequence/Index", url), &" ".repeat(257)).await.0, 413);
+    }
+
+    #[test]
+    fn peak_normalize_a_sine() {
+        let sine = Sine::new(3.0, std::f64::consts::FRAC_PI_2, 0.0);
+        assert_close(&first(PeakNormalize::new(sine), 5), &[0.0, 1.0, 0.0, -1.0, 0.0]);
+        // The peak is the one of the requested range, not of the whole sequence.
+        let values = first(PeakNormalize::new(Sine::new(3.0, 0.1, 0.0)), 5);
+        assert_eq!(values[4], 1.0);
+        assert_close(&values, &(0..5).map(|k| (0.1 * k as f64).sin() / 0.4f64.sin()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn peak_normalize_all_zeros_is_zeros() {
+        assert_eq!(first(PeakNormalize::new(Constant::new(0.0)), 3), [0.0; 3]);
+    }
+
+    #[test]
+    fn token_buckets_are_per_client() {
+        let state = State::new(Config { rate_limit: 0.5, rate_burst: 2.0, ..config() });
+        let (a, b): (IpAddr, IpAddr) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        assert!(state.take_token(a).is_ok() && state.take_token(a).is_ok());
+        // At 0.5 tokens per second the next one is about 2 seconds off.
+        let wait = state.take_token(a).unwrap_err();
+        assert!(wait > Duration::from_millis(1900) && wait <= Duration::from_secs(2), "{:?}", wait);
+        assert!(state.take_token(b).is_ok());
+
+        let unlimited = State::new(config());
+        assert!((0..1000).all(|_| unlimited.take_token(a).is_ok()));
+    }
+
+    #[tokio::test]
+    async fn too_many_requests() {
+        let url = generator(Config { rate_limit: 0.1, rate_burst: 3.0, ..config() }).await;
+        for _ in 0..3 {
+            assert_eq!(get(&format!("{}/ping", url)).await.0, 200);
+        }
+        let response = reqwest::get(format!("{}/ping", url)).await.unwrap();
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers()[RETRY_AFTER], "10");
+        assert!(response.text().await.unwrap().contains("Too many requests"));
+    }
+
+    #[test]
+    fn full_buckets_are_dropped_now_and_then() {
+        let state = State::new(Config { rate_limit: 1000.0, rate_burst: 1.0, ..config() });
+        for i in 0..10 {
+            state.take_token([10, 0, 0, i].into()).unwrap();
+        }
+        // Full again after a millisecond, but not yet swept.
+        std::thread::sleep(Duration::from_millis(5));
+        state.take_token([10, 0, 1, 0].into()).unwrap();
+        assert_eq!(state.rate_limits.lock().unwrap().buckets.len(), 11);
+
+        let long_ago = Instant::now().checked_sub(Duration::from_secs(RATE_SWEEP_SECS)).unwrap();
+        state.rate_limits.lock().unwrap().swept = long_ago;
+        std::thread::sleep(Duration::from_millis(5));
+        state.take_token([10, 0, 1, 1].into()).unwrap();
+        assert_eq!(state.rate_limits.lock().unwrap().buckets.len(), 1);
+    }
+
+    #[test]
+    fn smallest_period_within_the_window() {
+        assert_eq!(detect_period(&[1.0, 2.0, 1.0, 2.0, 1.0, 2.0], 0.0), Some(2));
+        // A period of 2 also means one of 4; the smaller one is found.
+        assert_eq!(detect_period(&[1.0; 8], 0.0), Some(1));
+        // Seen only once in the window, so not yet a period.
+        assert_eq!(detect_period(&[1.0, 2.0, 3.0, 1.0, 2.0], 0.0), None);
+        assert_eq!(detect_period(&[1.0, 2.0, 1.01, 2.0], 0.0), None);
+        assert_eq!(detect_period(&[1.0, 2.0, 1.01, 2.0], 0.1), Some(2));
+    }
+
+    #[test]
+    fn analyses_are_split_off_once() {
+        assert_eq!(Analysis::split("/sequence/Foo/period"), Some(("/sequence/Foo", Analysis::Period)));
+        assert_eq!(Analysis::split("/sequence/Foo/period/period"), Some(("/sequence/Foo/period", Analysis::Period)));
+        assert_eq!(Analysis::split("/sequence/Foo/limit"), Some(("/sequence/Foo", Analysis::Limit)));
+        assert_eq!(Analysis::split("/sequence/Foo/validate"), Some(("/sequence/Foo", Analysis::Validate)));
+        assert_eq!(Analysis::split("/sequence/Foo"), None);
+    }
+
+    #[tokio::test]
+    async fn period_of_cyclic_and_arithmetic() {
+        let url = generator(config()).await;
+        let period = |name: &str| format!("{}/sequence/{}/period", url, name);
+        let cyclic = r#"{"parameters":[3.0],"sequences":[{"name":"Arithmetic","parameters":[5.0,2.0],"sequences":[]}]}"#;
+        assert_eq!(post(&period("Cyclic"), cyclic).await, (200, r#"{"period":3}"#.to_string()));
+        let arithmetic = r#"{"parameters":[0.0,1.0],"sequences":[],"window":10000}"#;
+        assert_eq!(post(&period("Arithmetic"), arithmetic).await, (200, r#"{"period":null}"#.to_string()));
+
+        // The window has to hold the period twice.
+        let short = r#"{"parameters":[3.0],"sequences":[{"name":"Index","parameters":[],"sequences":[]}],"window":5}"#;
+        assert_eq!(post(&period("Cyclic"), short).await, (200, r#"{"period":null}"#.to_string()));
+        for window in [1, 10_001] {
+            let body = format!(r#"{{"parameters":[0.0,1.0],"sequences":[],"window":{}}}"#, window);
+            let (status, body) = post(&period("Arithmetic"), &body).await;
+            assert_eq!(status, 400);
+            assert!(body.contains("window must be between 2 and 10000"), "{}", body);
+        }
+        let (status, _) = post(&format!("{}/sequence/Arithmetic/period/period", url), r#"{"parameters":[0.0,1.0],"sequences":[]}"#).await;
+        assert_eq!(status, 404);
+    }
+}
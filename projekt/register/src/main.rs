@@ -21,6 +21,8 @@ pub struct PublicProject {
     pub name: String,
     pub ip: String,
     pub port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
 }
 
 async fn collect_body(req: Request<Incoming>) -> Result<String, hyper::Error> {
@@ -81,7 +83,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         let body = collect_body(req).await?;
                         let project: PublicProject = serde_json::from_str(&body).unwrap();
                         let mut db = db.lock().unwrap();
-                        db.insert(project.name.clone(), project);
+                        // Projects that send an id are deduplicated by it, the rest by name.
+                        let key = project.id.clone().unwrap_or_else(|| project.name.clone());
+                        db.insert(key, project);
                         println!("Got: {:?}", db);
                         Ok(Response::new(full("")))
                     }